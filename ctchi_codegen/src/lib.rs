@@ -5,35 +5,144 @@ use quote::quote;
 use syn::*;
 use syn::export::Span;
 
+/// Built-in argument types which implement `ctchi::core::http::FromRequest`. Other types
+/// are used as extractors when argument is marked with `#[from_request]`.
+const EXTRACTORS: [&str; 3] = ["Form", "Multipart", "Json"];
+
+/// Route for any HTTP method, `GET` if method isn't specified.
+///
+/// `#[route("/login/", method = "POST")]`
 #[proc_macro_attribute]
 pub fn route(args: TokenStream, input: TokenStream) -> TokenStream {
-    let original_func = syn::parse_macro_input!(input as syn::ItemFn);
+    let attrs = parse_macro_input!(args as AttributeArgs);
+
+    let mut method = "GET".to_string();
+    for attr in attrs.iter().skip(1) {
+        match attr {
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue { path, lit: Lit::Str(lit), .. }))
+                if path.is_ident("method") => method = lit.value().to_uppercase(),
+            _ => panic!("Unknown route attribute, only `method = \"...\"` is supported"),
+        }
+    }
+
+    build_route(route_path(&attrs), &method, input)
+}
+
+/// Route for `GET` requests, `HEAD` is handled by the same route.
+#[proc_macro_attribute]
+pub fn get(args: TokenStream, input: TokenStream) -> TokenStream {
+    let attrs = parse_macro_input!(args as AttributeArgs);
+    build_route(route_path(&attrs), "GET", input)
+}
+
+/// Route for `POST` requests.
+#[proc_macro_attribute]
+pub fn post(args: TokenStream, input: TokenStream) -> TokenStream {
+    let attrs = parse_macro_input!(args as AttributeArgs);
+    build_route(route_path(&attrs), "POST", input)
+}
+
+/// Route for `PUT` requests.
+#[proc_macro_attribute]
+pub fn put(args: TokenStream, input: TokenStream) -> TokenStream {
+    let attrs = parse_macro_input!(args as AttributeArgs);
+    build_route(route_path(&attrs), "PUT", input)
+}
+
+/// Route for `DELETE` requests.
+#[proc_macro_attribute]
+pub fn delete(args: TokenStream, input: TokenStream) -> TokenStream {
+    let attrs = parse_macro_input!(args as AttributeArgs);
+    build_route(route_path(&attrs), "DELETE", input)
+}
 
+/// Route for `PATCH` requests.
+#[proc_macro_attribute]
+pub fn patch(args: TokenStream, input: TokenStream) -> TokenStream {
     let attrs = parse_macro_input!(args as AttributeArgs);
+    build_route(route_path(&attrs), "PATCH", input)
+}
 
-    let path = match &attrs[0] {
-        NestedMeta::Lit(syn::Lit::Str(lit)) => Ok(lit.value()),
+/// Route path is always the first attribute argument.
+fn route_path(attrs: &AttributeArgs) -> String {
+    match attrs.first() {
+        Some(NestedMeta::Lit(syn::Lit::Str(lit))) => Ok(lit.value()),
         _ => Err("No path"),
-    }.unwrap();
+    }.unwrap()
+}
+
+fn build_route(path: String, method: &str, input: TokenStream) -> TokenStream {
+    let mut original_func = syn::parse_macro_input!(input as syn::ItemFn);
+
+    let method_ident = match method {
+        "GET" | "POST" | "PUT" | "DELETE" | "OPTIONS" | "HEAD" | "CONNECT" | "TRACE" | "PATCH" => {
+            Ident::new(method, Span::call_site())
+        }
+        _ => panic!("Unknown HTTP method '{}'", method),
+    };
+
+    // `#[from_request]` isn't a real attribute, so it's removed from handler
+    let marked = take_from_request_marks(&mut original_func.sig.inputs);
 
     let original_func_ident = &original_func.sig.ident;
     let inputs = &original_func.sig.inputs;
 
-    let mut fun_args = Vec::<Ident>::with_capacity(inputs.len());
-    let mut fun_args_str = Vec::<String>::with_capacity(inputs.len());
+    let path_params = route_params(&path);
+
+    // path captures are bound by name, arguments which aren't in path are taken from
+    // query string, `Request` parameter gets the request itself and extractors like
+    // `Form<T>` are built from request
+    let mut bindings = Vec::<proc_macro2::TokenStream>::with_capacity(inputs.len());
+    let mut call_args = Vec::<proc_macro2::TokenStream>::with_capacity(inputs.len());
+    let mut query_used = false;
 
-    for i in inputs {
-        let input_pat = match i {
-            FnArg::Typed(PatType{attrs, pat, colon_token, ty}) => pat,
-            _ => panic!(""),
+    for (position, i) in inputs.iter().enumerate() {
+        let (input_pat, input_ty) = match i {
+            FnArg::Typed(PatType { pat, ty, .. }) => (pat, ty),
+            _ => panic!("Route handler can't have self argument"),
         };
 
-        if let syn::Pat::Ident(PatIdent { attrs, by_ref, mutability, ident, subpat }) = input_pat.as_ref() {
-            fun_args.push(Ident::new(&ident.to_string(), Span::call_site()));
-            fun_args_str.push(ident.to_string());
+        if marked[position] || is_extractor(input_ty) {
+            // extractor can be destructured in handler, so it gets generated name
+            let arg = Ident::new(&format!("ctchi_arg_{}", position), Span::call_site());
+            bindings.push(quote! {
+                let #arg: #input_ty = match <#input_ty as ctchi::core::http::FromRequest>::from_request(ctchi_request) {
+                    Ok(value) => value,
+                    Err(response) => return response,
+                };
+            });
+            call_args.push(quote! { #arg });
+            continue;
+        }
+
+        if let syn::Pat::Ident(PatIdent { ident, .. }) = input_pat.as_ref() {
+            match request_kind(input_ty) {
+                Some(RequestKind::Reference) => call_args.push(quote! { ctchi_request }),
+                Some(RequestKind::Value) => call_args.push(quote! { ctchi_request.clone() }),
+                None => {
+                    let name = ident.to_string();
+                    let arg = Ident::new(&name, Span::call_site());
+                    if path_params.contains(&name) {
+                        bindings.push(path_param_binding(&arg, &name, input_ty));
+                    } else {
+                        bindings.push(query_param_binding(&arg, &name, input_ty));
+                        query_used = true;
+                    }
+                    call_args.push(quote! { #arg });
+                }
+            }
+        } else {
+            panic!("Route handler argument should be a plain name or an extractor like `Form<T>`");
         }
     }
 
+    // query string is parsed once for all query arguments
+    let query = if query_used {
+        quote! { let ctchi_query = ctchi_request.query(); }
+    } else {
+        quote! {}
+    };
+
     let action_ident = Ident::new(
         &format!("ctchi_action_{}", original_func_ident),
         Span::call_site()
@@ -46,24 +155,18 @@ pub fn route(args: TokenStream, input: TokenStream) -> TokenStream {
     let gen = quote! {
         #original_func
 
-        fn #action_ident(url: &str) -> String {
-            use regex::Regex;
-
-            let url_replacer = Regex::new(r"\{(?P<first>.+?)\}").unwrap();
-            let regex_url = url_replacer.replace_all(#path, "(?P<$first>.+?)");
-
-            let parser = Regex::new(&regex_url).unwrap();
+        fn #action_ident(ctchi_request: &ctchi::core::http::Request) -> ctchi::core::http::Response {
+            #query
 
-            #(
-                let #fun_args = parser.captures(url).unwrap().name(#fun_args_str).unwrap().as_str();
-            )*
+            #(#bindings)*
 
-            #original_func_ident(#(#fun_args),*)
+            ctchi::core::http::IntoResponse::into_response(#original_func_ident(#(#call_args),*))
         }
 
         fn #routing_ident() -> Route {
             Route {
                 path: #path.to_string(),
+                method: ctchi::core::http::HttpMethod::#method_ident,
                 render_action: #action_ident,
             }
         }
@@ -71,3 +174,196 @@ pub fn route(args: TokenStream, input: TokenStream) -> TokenStream {
 
     gen.into()
 }
+
+/// How handler wants to receive `ctchi::core::http::Request`.
+enum RequestKind {
+    Reference,
+    Value,
+}
+
+/// Check if argument type is `Request` or `&Request` (with any path prefix).
+fn request_kind(ty: &Type) -> Option<RequestKind> {
+    match ty {
+        Type::Reference(TypeReference { elem, .. }) => {
+            request_kind(elem).map(|_| RequestKind::Reference)
+        }
+        Type::Path(TypePath { path, .. }) => {
+            match path.segments.last() {
+                Some(segment) if segment.ident == "Request" => Some(RequestKind::Value),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Get names of route parameters, `["id"]` for `/blog/{id:[0-9]+}/`.
+fn route_params(path: &str) -> Vec<String> {
+    path.split('/')
+        .filter(|s| s.starts_with('{') && s.ends_with('}'))
+        .map(|s| {
+            let inner = &s[1..s.len() - 1];
+            let name = inner.split(':').next().unwrap_or("");
+            name.trim_start_matches('*').trim().to_string()
+        })
+        .collect()
+}
+
+/// Bind path parameter to handler argument. `&str` arguments get raw value, any other type
+/// is parsed with `FromStr` and parse error means there is no such page.
+fn path_param_binding(arg: &Ident, name: &str, ty: &Type) -> proc_macro2::TokenStream {
+    if is_str_reference(ty) {
+        return quote! {
+            let #arg: &str = ctchi_request.param(#name).unwrap_or("");
+        };
+    }
+
+    quote! {
+        let #arg: #ty = match ctchi_request.param(#name).unwrap_or("").parse::<#ty>() {
+            Ok(value) => value,
+            Err(_) => return ctchi::core::http::Response::new(404),
+        };
+    }
+}
+
+/// Bind query parameter to handler argument. Only the first value is used when
+/// parameter is repeated. `Option<T>` arguments are optional, others are required,
+/// missing required parameter or parse error is a bad request.
+fn query_param_binding(arg: &Ident, name: &str, ty: &Type) -> proc_macro2::TokenStream {
+    let bad_request = quote! { return ctchi::core::http::Response::new(400) };
+    let value = quote! { ctchi_query.get(#name).and_then(|values| values.first()) };
+
+    match option_inner(ty) {
+        Some(inner) if is_str_reference(inner) => quote! {
+            let #arg: #ty = #value.map(|value| value.as_str());
+        },
+        Some(inner) => quote! {
+            let #arg: #ty = match #value.map(|value| value.parse::<#inner>()) {
+                Some(Ok(value)) => Some(value),
+                Some(Err(_)) => #bad_request,
+                None => None,
+            };
+        },
+        None if is_str_reference(ty) => quote! {
+            let #arg: &str = match #value {
+                Some(value) => value.as_str(),
+                None => #bad_request,
+            };
+        },
+        None => quote! {
+            let #arg: #ty = match #value.map(|value| value.parse::<#ty>()) {
+                Some(Ok(value)) => value,
+                _ => #bad_request,
+            };
+        },
+    }
+}
+
+/// Remove `#[from_request]` from handler arguments, result tells which arguments had it.
+fn take_from_request_marks(inputs: &mut punctuated::Punctuated<FnArg, token::Comma>) -> Vec<bool> {
+    inputs.iter_mut()
+        .map(|input| match input {
+            FnArg::Typed(PatType { attrs, .. }) => {
+                let count = attrs.len();
+                attrs.retain(|attr| !attr.path.is_ident("from_request"));
+                attrs.len() != count
+            }
+            _ => false,
+        })
+        .collect()
+}
+
+/// Check if argument type is one of built-in `ctchi::core::http::FromRequest` types.
+fn is_extractor(ty: &Type) -> bool {
+    match ty {
+        Type::Path(TypePath { path, .. }) => {
+            match path.segments.last() {
+                Some(segment) => EXTRACTORS.iter().any(|e| segment.ident == e),
+                None => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+/// Get `T` from `Option<T>` argument type.
+fn option_inner(ty: &Type) -> Option<&Type> {
+    generic_inner(ty, "Option")
+}
+
+/// Get `T` from `Wrapper<T>` type, e.g. from `Vec<T>`.
+fn generic_inner<'a>(ty: &'a Type, wrapper: &str) -> Option<&'a Type> {
+    let segment = match ty {
+        Type::Path(TypePath { path, .. }) => path.segments.last()?,
+        _ => return None,
+    };
+
+    if segment.ident != wrapper {
+        return None;
+    }
+
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) => match args.args.first()? {
+            GenericArgument::Type(inner) => Some(inner),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Check if argument type is `&str`.
+fn is_str_reference(ty: &Type) -> bool {
+    match ty {
+        Type::Reference(TypeReference { elem, .. }) => {
+            matches!(elem.as_ref(), Type::Path(TypePath { path, .. }) if path.is_ident("str"))
+        }
+        _ => false,
+    }
+}
+
+/// Implement `ctchi::core::form::FromForm` for struct with named fields.
+///
+/// `Option<T>` fields are optional, `Vec<T>` fields take all values of repeated field,
+/// `bool` fields are checkboxes, other fields are required and parsed with `FromStr`.
+#[proc_macro_derive(FromForm)]
+pub fn from_form(input: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(input as DeriveInput);
+    let name = &item.ident;
+
+    let fields = match &item.data {
+        Data::Struct(DataStruct { fields: Fields::Named(fields), .. }) => &fields.named,
+        _ => panic!("FromForm can be derived only for struct with named fields"),
+    };
+
+    let values = fields.iter().map(|field| {
+        let ident = field.ident.as_ref().unwrap();
+        let field_name = ident.to_string();
+        let ty = &field.ty;
+
+        let getter = if option_inner(ty).is_some() {
+            quote! { ctchi::core::form::optional(fields, #field_name)? }
+        } else if generic_inner(ty, "Vec").is_some() {
+            quote! { ctchi::core::form::multiple(fields, #field_name)? }
+        } else if matches!(ty, Type::Path(TypePath { path, .. }) if path.is_ident("bool")) {
+            quote! { ctchi::core::form::flag(fields, #field_name) }
+        } else {
+            quote! { ctchi::core::form::required(fields, #field_name)? }
+        };
+
+        quote! { #ident: #getter }
+    });
+
+    let gen = quote! {
+        impl ctchi::core::form::FromForm for #name {
+            fn from_form(
+                fields: &ctchi::core::form::FormFields,
+            ) -> Result<Self, ctchi::core::form::FormError> {
+                Ok(#name {
+                    #(#values),*
+                })
+            }
+        }
+    };
+
+    gen.into()
+}
@@ -1,136 +1,777 @@
 use std::net::{TcpListener, TcpStream};
-use std::io::{Read, Write, BufReader, BufRead};
+use std::io::{self, Read, Write, BufReader, BufRead, ErrorKind};
 use std::sync::Arc;
 use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use super::routes::Routes;
-use super::http::{HttpMethod, Request};
-use super::thread_pool::{ThreadPool};
+use super::routes::{Routes, RouteMatch};
+use super::http::{HttpMethod, Request, Response};
+use super::thread_pool::{QueueDepth, QueueOverflow, ThreadPool};
+use super::static_files;
+use super::shutdown::{self, ShutdownHandle};
+use super::compression;
+use super::multipart::{self, Multipart, MultipartError, MultipartLimits};
 
-use crate::core::config::get_configuration;
+use crate::core::config::{self, get_configuration, Config};
 use crate::log::logger;
 
-struct RequestHandler;
+/// Pause after failed `accept`. Errors like `EMFILE` don't go away until some connection
+/// is closed, retrying at once would only burn CPU and flood the log.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(50);
 
-fn read_static(file_pth: &str) -> impl Fn(&str) -> String + '_ {
-    move |pref| -> String {
-        use std::fs;
-        let content = fs::read_to_string(format!("{}/{}", pref, file_pth))
-            .unwrap_or_else(|error| { error.to_string() });
-        content
+/// Everything connection handler needs, shared by all workers of application.
+struct RequestHandler {
+    routes: Routes,
+    config: Arc<Config>,
+    shutdown: ShutdownHandle,
+}
+
+/// Reasons why we can't build `Request` from incoming bytes.
+#[derive(Debug)]
+enum RequestError {
+    /// Connection was closed or timed out before the first byte of request.
+    Closed,
+    BadRequest,
+    /// Request line and headers weren't received in time.
+    Timeout,
+    PayloadTooLarge,
+    /// Request line and headers are bigger than `max_header_size` or there are too many headers.
+    HeadersTooLarge,
+    /// Transfer coding other than `chunked`.
+    NotImplemented,
+    /// Uploaded file can't be stored.
+    Internal,
+}
+
+impl RequestError {
+    /// Response for the error, `None` if connection should be closed silently.
+    fn to_response(&self) -> Option<Response> {
+        let code = match self {
+            RequestError::Closed => return None,
+            RequestError::BadRequest => 400,
+            RequestError::Timeout => 408,
+            RequestError::PayloadTooLarge => 413,
+            RequestError::HeadersTooLarge => 431,
+            RequestError::NotImplemented => 501,
+            RequestError::Internal => 500,
+        };
+
+        // we don't know where the broken request ends, so connection can't be reused
+        Some(Response::new(code).with_header("Connection", "close"))
+    }
+}
+
+impl From<io::Error> for RequestError {
+    fn from(error: io::Error) -> RequestError {
+        match error.kind() {
+            ErrorKind::TimedOut | ErrorKind::WouldBlock => RequestError::Timeout,
+            _ => RequestError::BadRequest,
+        }
+    }
+}
+
+/// Parse number which consists of digits only. `str::parse` also accepts leading `+`,
+/// and proxy which doesn't could see another body length.
+fn parse_number(value: &str, radix: u32) -> Option<usize> {
+    if value.is_empty() || !value.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    usize::from_str_radix(value, radix).ok()
+}
+
+/// Check that request target has only characters allowed in URI (RFC 3986), so control
+/// characters, spaces and raw non-ASCII bytes never get to routing and headers.
+fn is_valid_target(uri: &str) -> bool {
+    uri.bytes().all(|b| b.is_ascii_alphanumeric() || b"-._~:/?#[]@!$&'()*+,;=%".contains(&b))
+}
+
+/// Read one line terminated by `\r\n` (or bare `\n`) without line ending.
+/// Unexpected end of stream and non UTF-8 lines are treated as bad request,
+/// lines longer than `limit` bytes (with line ending) as too large headers.
+fn read_crlf_line<R: BufRead>(reader: &mut R, limit: usize) -> Result<String, RequestError> {
+    let mut line = Vec::new();
+    let read = reader.by_ref().take(limit as u64).read_until(b'\n', &mut line)?;
+    if !line.ends_with(b"\n") {
+        return Err(if read == limit { RequestError::HeadersTooLarge } else { RequestError::BadRequest });
+    }
+
+    line.pop();
+    if line.ends_with(b"\r") {
+        line.pop();
+    }
+
+    String::from_utf8(line).map_err(|_| RequestError::BadRequest)
+}
+
+/// Get timeout from configuration value in seconds, `0` means no timeout.
+fn timeout(seconds: u64) -> Option<Duration> {
+    if seconds > 0 { Some(Duration::from_secs(seconds)) } else { None }
+}
+
+/// Connection stream with deadlines for reading and writing. Socket timeouts limit only one
+/// system call, so client sending one byte at a time would never hit them.
+struct TimedStream {
+    stream: TcpStream,
+    read_deadline: Option<Instant>,
+    write_deadline: Option<Instant>,
+}
+
+impl TimedStream {
+    fn new(stream: TcpStream) -> TimedStream {
+        TimedStream { stream, read_deadline: None, write_deadline: None }
+    }
+
+    /// Following reads fail with `ErrorKind::TimedOut` after `timeout` from now.
+    fn read_within(&mut self, timeout: Option<Duration>) {
+        self.read_deadline = timeout.map(|timeout| Instant::now() + timeout);
+    }
+
+    /// Following writes fail with `ErrorKind::TimedOut` after `timeout` from now.
+    fn write_within(&mut self, timeout: Option<Duration>) {
+        self.write_deadline = timeout.map(|timeout| Instant::now() + timeout);
+    }
+}
+
+/// Time left till deadline, error if it's passed already.
+fn time_left(deadline: Option<Instant>) -> io::Result<Option<Duration>> {
+    match deadline {
+        Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+            Some(left) if !left.is_zero() => Ok(Some(left)),
+            _ => Err(io::Error::new(ErrorKind::TimedOut, "deadline has passed")),
+        },
+        None => Ok(None),
+    }
+}
+
+impl Read for TimedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.set_read_timeout(time_left(self.read_deadline)?)?;
+        self.stream.read(buf)
+    }
+}
+
+impl Write for TimedStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.set_write_timeout(time_left(self.write_deadline)?)?;
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
     }
 }
 
 impl RequestHandler {
-    fn handle_request(&self, stream: TcpStream, routes: Arc<Routes>) {
-        let mut reader = BufReader::new(stream);
+    /// Serve connection. Requests are read one after another from the same stream, so
+    /// pipelined requests are answered in order. Connection is closed when client asks for it,
+    /// after `keep_alive_max_requests` requests, after broken request or when client doesn't
+    /// send anything for `keep_alive_timeout` seconds. During shutdown every response closes connection.
+    fn handle_request(&self, stream: TcpStream) {
+        let config = &*self.config;
+        // controllers, templates and extractors read configuration of this application
+        let _scope = config::enter(Arc::clone(&self.config));
+
+        let mut reader = BufReader::new(TimedStream::new(stream));
+
+        let mut served = 0;
+        loop {
+            // new connection should start with request, later ones can wait for the next request
+            let idle_timeout = if served == 0 { config.header_timeout } else { config.keep_alive_timeout };
+            reader.get_mut().read_within(timeout(idle_timeout));
+
+            served += 1;
+            let (is_head, keep_alive, mut response) = match self.parse_request(&mut reader, config) {
+                Ok((request, uploads)) => {
+                    // controller gets parsed multipart body through `Multipart` argument
+                    let _uploads = uploads.map(multipart::enter);
+                    // request with both Transfer-Encoding and Content-Length may be a smuggling
+                    // attempt, so connection is closed after it (RFC 9112, section 6.1)
+                    let ambiguous = request.header("transfer-encoding").is_some()
+                        && request.header("content-length").is_some();
+                    let keep_alive = request.keep_alive()
+                        && !ambiguous
+                        && !self.shutdown.is_shutdown()
+                        && config.keep_alive_timeout > 0
+                        && served < config.keep_alive_max_requests;
+                    let is_head = request.method == HttpMethod::HEAD;
+                    // panicking controller shouldn't leave client without answer
+                    let response = panic::catch_unwind(AssertUnwindSafe(|| self.respond(request, &self.routes, config)))
+                        .unwrap_or_else(|_| {
+                            log::info!("Controller panicked");
+                            Response::new(500).with_header("Connection", "close")
+                        });
+                    (is_head, keep_alive, response)
+                }
+                Err(error) => match error.to_response() {
+                    Some(response) => {
+                        log::info!("Can't parse request: {}", response.code);
+                        (false, false, response)
+                    }
+                    // client closed connection or kept it idle for too long
+                    None => break,
+                },
+            };
+            log::info!("Response: {} ({} bytes)", response.code, response.content_length());
 
-        let request = self.parse_request(&mut reader);
+            // controller can close connection itself
+            let keep_alive = keep_alive && !response.header("connection")
+                .map(|value| value.eq_ignore_ascii_case("close"))
+                .unwrap_or(false);
+            // HTTP/1.0 clients need explicit confirmation, for HTTP/1.1 it's harmless
+            response.set_header("Connection", if keep_alive { "keep-alive" } else { "close" });
 
+            let stream = reader.get_mut();
+            stream.write_within(timeout(config.write_timeout));
+            // HEAD response has the same headers as GET, but without body
+            let written = response.write_to(stream, !is_head).and_then(|_| stream.flush());
+            if let Err(error) = written {
+                log::info!("{}", error);
+                break;
+            }
+
+            if !keep_alive {
+                break;
+            }
+        }
+    }
+
+    /// Build response for parsed request: static file, controller result or routing error.
+    fn respond(&self, mut request: Request, routes: &Routes, config: &Config) -> Response {
         log::info!("Request: {:?} {}", request.method, request.url);
         if !request.body.is_empty() {
-            log::info!("{}", request.body);
+            log::info!("{}", String::from_utf8_lossy(&request.body));
         }
 
-        let config_reader = get_configuration();
-        let config = config_reader.inner.lock().unwrap();
-        let tmp_base_path = config.base_path.to_string();
-        let prefix = config.static_uri_pref.to_string();
-        drop(config);
-
-        let content = if request.url.starts_with(&prefix) {
-            read_static(&request.url)(tmp_base_path.as_str())
+        let prefix = &config.static_uri_pref;
+        let response = if request.url.starts_with(prefix.as_str()) {
+            let static_root = Path::new(&config.base_path).join(prefix.trim_matches('/'));
+            static_files::serve(&request, &static_root, &request.url[prefix.len()..], config)
         } else {
-            (routes.get_route(request.url.as_ref()).render_action)(request.url.as_ref())
+            match routes.get_route(&request.method, request.url.as_ref()) {
+                RouteMatch::Found(route, params) => {
+                    request.params = params;
+                    (route.render_action)(&request)
+                }
+                RouteMatch::NotFound(route) => (route.render_action)(&request),
+                RouteMatch::Redirect(path) => {
+                    let location = if request.query_string.is_empty() {
+                        path
+                    } else {
+                        format!("{}?{}", path, request.query_string)
+                    };
+                    // 308 keeps method and body, old clients know only 301 for GET
+                    let code = match request.method {
+                        HttpMethod::GET | HttpMethod::HEAD => 301,
+                        _ => 308,
+                    };
+                    Response::new(code).with_header("Location", &location)
+                }
+                RouteMatch::MethodNotAllowed(allowed) => {
+                    let allow = allowed.iter()
+                        .map(|m| m.as_str())
+                        .collect::<Vec<&str>>()
+                        .join(", ");
+                    Response::new(405).with_header("Allow", &allow)
+                }
+            }
         };
 
-        let response = format!(
-            "HTTP/1.1 200 OK\r\n\r\n{}",
-            content
-        );
-        log::info!("Response: {}", response);
-
-        let mut reader_stream = reader.into_inner();
-        let result = reader_stream.write(response.as_bytes());
-        result.unwrap_or_else(|error| {
-            log::info!("{}", error);
-            0
-        });
-
-        reader_stream.flush().unwrap_or_else(|error| {
-            log::info!("{}", error);
-        });
+        compression::compress(&request, response, config.compression_threshold)
     }
 
     /// Parse stream of bytes in Request object.
     /// Gets URI, HTTP method, headers and body.
-    fn parse_request(&self, reader: &mut BufReader<TcpStream>) -> Request {
-        let mut lines = reader.by_ref().lines();
+    ///
+    /// Body is read exactly by `Content-Length` header, requests without it have empty body.
+    /// `multipart/form-data` bodies aren't kept in memory, they are parsed while reading with
+    /// `multipart_*` limits instead of `max_body_size` and returned separately.
+    /// Several `Content-Length` headers with different values give `RequestError::BadRequest`.
+    /// `Transfer-Encoding` other than `chunked` gives `RequestError::NotImplemented`.
+    /// Malformed request line or headers give `RequestError::BadRequest`, connection closed
+    /// before request gives `RequestError::Closed`.
+    ///
+    /// Request line and headers should come in `header_timeout` after the first byte and be
+    /// not bigger than `max_header_size`, body should come in `body_timeout`.
+    ///
+    /// # Arguments:
+    /// * `reader` - buffered connection stream, read deadline is set for waiting the first byte
+    /// * `config` - configuration with limits, bodies bigger than `max_body_size` are rejected
+    ///   with `RequestError::PayloadTooLarge` before reading them
+    fn parse_request(
+        &self,
+        reader: &mut BufReader<TimedStream>,
+        config: &Config,
+    ) -> Result<(Request, Option<Multipart>), RequestError> {
+        let mut header_budget = config.max_header_size;
+        let mut started = false;
+
+        let request_line = loop {
+            // nothing came before connection was closed or timed out, so there is nobody to answer
+            match reader.fill_buf() {
+                Ok(buffer) if !buffer.is_empty() => {}
+                _ => return Err(RequestError::Closed),
+            }
+            if !started {
+                reader.get_mut().read_within(timeout(config.header_timeout));
+                started = true;
+            }
+
+            // empty lines before request line should be ignored
+            let line = read_crlf_line(reader, header_budget)?;
+            header_budget = header_budget.saturating_sub(line.len() + 2);
+            if !line.is_empty() {
+                break line;
+            }
+        };
 
-        // get method line, it should be in every request, so unwrapping is safe
-        // fixme thread '<unnamed>' panicked at 'called `Option::unwrap()` on a `None` value',
-        // /Users/glotitude/.cargo/registry/src/github.com-1ecc6299db9ec823/ctchi-0.19.0/src/core/app.rs:69:40
-        // for some reason
-        let method_line = lines.next().unwrap().unwrap();
-        let method = method_line.split(" ").collect::<Vec<&str>>();
-        let http_method = HttpMethod::parse(method[0]);
+        // method, request target and protocol version separated by single spaces
+        let request_line = request_line.split(' ').collect::<Vec<&str>>();
+        let (method, uri, version) = match request_line[..] {
+            [method, uri, version]
+                if !method.is_empty() && !uri.is_empty() && is_valid_target(uri) && version.starts_with("HTTP/1.") => {
+                (method, uri, version)
+            }
+            _ => return Err(RequestError::BadRequest),
+        };
 
         let mut headers = HashMap::<String, String>::new();
-        for line in lines {
-            let l = line.unwrap();
+        // repeated names are merged, so lines are counted, not map entries
+        let mut header_count = 0;
+        loop {
+            let line = read_crlf_line(reader, header_budget)?;
+            header_budget = header_budget.saturating_sub(line.len() + 2);
 
-            // fixme we will miss body in this case
-            if l == String::from("") {
+            // headers are over, body (if any) goes next
+            if line.is_empty() {
                 break;
             }
+            header_count += 1;
+            if header_count > config.max_header_count {
+                return Err(RequestError::HeadersTooLarge);
+            }
 
-            let parts = l.split(":").collect::<Vec<&str>>();
-            if parts.len() == 2 {
-                headers.insert(parts[0].to_string(), parts[1].to_string());
+            // whitespace before colon and obsolete line folding aren't allowed
+            match line.split_once(':') {
+                Some((name, value)) if !name.is_empty() && !name.contains(char::is_whitespace) => {
+                    let name = name.to_lowercase();
+                    let value = value.trim().to_string();
+                    match headers.get_mut(&name) {
+                        // all codings count, not only the last header with them
+                        Some(codings) if name == "transfer-encoding" => {
+                            codings.push_str(", ");
+                            codings.push_str(&value);
+                        }
+                        // request can't be for two hosts (RFC 9112, section 3.2)
+                        Some(_) if name == "host" => return Err(RequestError::BadRequest),
+                        // body length must be unambiguous, identical copies are harmless
+                        Some(length) if name == "content-length" => {
+                            if *length != value {
+                                return Err(RequestError::BadRequest);
+                            }
+                        }
+                        _ => {
+                            headers.insert(name, value);
+                        }
+                    }
+                }
+                _ => return Err(RequestError::BadRequest),
+            }
+        }
+
+        reader.get_mut().read_within(timeout(config.body_timeout));
+        let max_body_size = config.max_body_size;
+        let boundary = headers.get("content-type").and_then(|content_type| multipart::boundary(content_type));
+        let limits = MultipartLimits::from_config(config);
+        let mut uploads = None;
+        let body = match headers.get("transfer-encoding") {
+            // transfer encoding takes precedence over content length, chunked is the only
+            // supported coding, e.g. `gzip, chunked` isn't
+            Some(encoding) => {
+                if !encoding.eq_ignore_ascii_case("chunked") {
+                    return Err(RequestError::NotImplemented);
+                }
+
+                match boundary {
+                    Some(boundary) => {
+                        let mut chunked = ChunkedReader::new(reader, config.max_header_size, config.max_header_count);
+                        let parsed = multipart::parse_stream(&mut chunked, &boundary, &limits);
+                        uploads = Some(parsed.map_err(|error| upload_error(error, chunked.error.take()))?);
+                        Vec::new()
+                    }
+                    None => self.read_chunked_body(reader, max_body_size, config.max_header_size, config.max_header_count)?,
+                }
+            }
+            None => {
+                let content_length = match headers.get("content-length") {
+                    Some(value) => parse_number(value, 10).ok_or(RequestError::BadRequest)?,
+                    None => 0,
+                };
+
+                match boundary {
+                    Some(boundary) => {
+                        if content_length > limits.max_total_size {
+                            return Err(RequestError::PayloadTooLarge);
+                        }
+
+                        let mut body = reader.by_ref().take(content_length as u64);
+                        let parsed = multipart::parse_stream(&mut body, &boundary, &limits);
+                        uploads = Some(parsed.map_err(|error| upload_error(error, None))?);
+                        Vec::new()
+                    }
+                    None => {
+                        if content_length > max_body_size {
+                            return Err(RequestError::PayloadTooLarge);
+                        }
+
+                        let mut body = vec![0; content_length];
+                        reader.read_exact(&mut body)?;
+                        body
+                    }
+                }
             }
         };
 
-        let mut url = if method.len() > 1 {
-            method[1].to_string()
-        } else {
-            String::new()
+        let (path, query_string) = uri.split_once('?').unwrap_or((uri, ""));
+        let request = Request {
+            method: HttpMethod::parse(method),
+            url: path.to_string(),
+            version: version.to_string(),
+            query_string: query_string.to_string(),
+            headers,
+            body,
+            params: HashMap::new(),
         };
+        Ok((request, uploads))
+    }
 
-        if !url.ends_with("/")
-            && !url.ends_with(".css")
-            && !url.ends_with(".js")
-            && !url.ends_with(".jpg")
-        {
-            url = format!("{}/", url);
+    /// Read whole body sent with `Transfer-Encoding: chunked`, see `ChunkedReader`.
+    ///
+    /// # Arguments:
+    /// * `reader` - buffered connection stream positioned right after request headers
+    /// * `max_body_size` - maximum allowed size of decoded body in bytes
+    /// * `max_line` - maximum size of chunk size line and of all trailers together in bytes
+    /// * `max_trailers` - maximum number of trailer fields
+    fn read_chunked_body<R: BufRead>(
+        &self,
+        reader: &mut R,
+        max_body_size: usize,
+        max_line: usize,
+        max_trailers: usize,
+    ) -> Result<Vec<u8>, RequestError> {
+        let mut chunked = ChunkedReader::new(reader, max_line, max_trailers);
+        let mut body = Vec::new();
+
+        loop {
+            let size = match chunked.fill_buf() {
+                Ok([]) => return Ok(body),
+                Ok(data) => {
+                    if data.len() > max_body_size - body.len() {
+                        return Err(RequestError::PayloadTooLarge);
+                    }
+                    body.extend_from_slice(data);
+                    data.len()
+                }
+                Err(error) => return Err(chunked.error.take().unwrap_or_else(|| error.into())),
+            };
+            chunked.consume(size);
         }
+    }
+}
 
-        Request {
-            method: http_method,
-            url,
-            headers,
-            body: String::new(),
+/// Reason to answer for multipart body which can't be parsed while reading.
+///
+/// # Arguments:
+/// * `error` - parsing error
+/// * `framing` - error of chunked encoding, it's the real reason if body can't be read
+fn upload_error(error: MultipartError, framing: Option<RequestError>) -> RequestError {
+    log::info!("{}", error);
+    if let Some(framing) = framing {
+        return framing;
+    }
+
+    match error {
+        MultipartError::FileTooLarge(_) | MultipartError::TooLarge => RequestError::PayloadTooLarge,
+        MultipartError::Io(_) => RequestError::Internal,
+        MultipartError::Read(kind) => RequestError::from(io::Error::from(kind)),
+        MultipartError::Malformed | MultipartError::NotMultipart => RequestError::BadRequest,
+    }
+}
+
+/// Body sent with `Transfer-Encoding: chunked`, decoded while reading.
+///
+/// Every chunk is `<hex size>[;extensions]\r\n<data>\r\n`, zero sized chunk ends the body
+/// and can be followed by trailer fields. Trailers are read and dropped: they come after
+/// the request was checked, so they mustn't turn into headers (RFC 9110, section 6.5).
+/// Reader ends after trailers, so connection is positioned at the next request.
+///
+/// Broken framing gives `ErrorKind::InvalidData`, the reason is kept in `error`.
+struct ChunkedReader<'a, R> {
+    reader: &'a mut R,
+    /// Bytes left in current chunk.
+    left: usize,
+    /// Data of chunk is over, so empty line goes next.
+    data_ended: bool,
+    done: bool,
+    /// Maximum size of chunk size line and of all trailers together in bytes.
+    max_line: usize,
+    max_trailers: usize,
+    error: Option<RequestError>,
+}
+
+impl<'a, R: BufRead> ChunkedReader<'a, R> {
+    fn new(reader: &'a mut R, max_line: usize, max_trailers: usize) -> ChunkedReader<'a, R> {
+        ChunkedReader {
+            reader,
+            left: 0,
+            data_ended: false,
+            done: false,
+            max_line,
+            max_trailers,
+            error: None,
+        }
+    }
+
+    /// Read size of the next chunk or, after the last one, trailers.
+    fn next_chunk(&mut self) -> Result<(), RequestError> {
+        if self.data_ended {
+            // every chunk data ends with empty line
+            if !read_crlf_line(self.reader, self.max_line)?.is_empty() {
+                return Err(RequestError::BadRequest);
+            }
+            self.data_ended = false;
+        }
+
+        let size_line = read_crlf_line(self.reader, self.max_line).map_err(|error| match error {
+            RequestError::HeadersTooLarge => RequestError::BadRequest,
+            error => error,
+        })?;
+        // chunk extensions are allowed, but we don't support any of them
+        let size_str = size_line.split(';').next().unwrap_or("").trim();
+        let size = parse_number(size_str, 16).ok_or(RequestError::BadRequest)?;
+
+        if size == 0 {
+            self.read_trailers()?;
+            self.done = true;
+        }
+        self.left = size;
+        Ok(())
+    }
+
+    fn read_trailers(&mut self) -> Result<(), RequestError> {
+        // trailers are limited like headers, so client can't send them until body timeout
+        let mut trailer_budget = self.max_line;
+        let mut trailers = 0;
+        loop {
+            let trailer = read_crlf_line(self.reader, trailer_budget)?;
+            trailer_budget = trailer_budget.saturating_sub(trailer.len() + 2);
+            if trailer.is_empty() {
+                return Ok(());
+            }
+
+            trailers += 1;
+            if trailers > self.max_trailers {
+                return Err(RequestError::HeadersTooLarge);
+            }
+            match trailer.split_once(':') {
+                Some((name, _)) if !name.is_empty() && !name.contains(char::is_whitespace) => {}
+                _ => return Err(RequestError::BadRequest),
+            }
         }
     }
 }
 
+impl<R: BufRead> Read for ChunkedReader<'_, R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let data = self.fill_buf()?;
+        let size = data.len().min(buffer.len());
+        buffer[..size].copy_from_slice(&data[..size]);
+        self.consume(size);
+        Ok(size)
+    }
+}
+
+impl<R: BufRead> BufRead for ChunkedReader<'_, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.left == 0 && !self.done {
+            if let Err(error) = self.next_chunk() {
+                self.error = Some(error);
+                return Err(io::Error::new(ErrorKind::InvalidData, "broken chunked body"));
+            }
+        }
+        if self.done {
+            return Ok(&[]);
+        }
+
+        let data = self.reader.fill_buf()?;
+        if data.is_empty() {
+            return Err(io::Error::from(ErrorKind::UnexpectedEof));
+        }
+        Ok(&data[..data.len().min(self.left)])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        if amount == 0 {
+            return;
+        }
+        self.reader.consume(amount);
+        self.left -= amount;
+        self.data_ended = self.left == 0;
+    }
+}
+
+/// Answer connection which doesn't fit into queue. Request isn't read,
+/// so the answer is the same for everyone.
+fn reject(mut stream: TcpStream) {
+    log::info!("Queue is full, connection is rejected");
+    let response = Response::new(503)
+        .with_header("Retry-After", "1")
+        .with_header("Connection", "close");
+
+    let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+    if let Err(error) = stream.write_all(&response.to_bytes()) {
+        log::info!("{}", error);
+    }
+}
+
 pub struct Ctchi {
     routes: Routes,
+    /// `None` for applications created with `Ctchi::new`, they take global configuration on start.
+    config: Option<Arc<Config>>,
+    shutdown: ShutdownHandle,
+    queue_depth: QueueDepth,
+}
+
+/// Builder of application with its own configuration.
+///
+/// # Example
+///
+/// ```rust
+/// use ctchi::core::app::Ctchi;
+/// use ctchi::core::config::Config;
+/// use ctchi::core::http::{HttpMethod, Request, Response};
+/// use ctchi::core::routes::{Route, Routes};
+/// use ctchi::core::shutdown::ShutdownHandle;
+/// use std::io::{Read, Write};
+/// use std::net::{SocketAddr, TcpStream};
+/// use std::thread;
+/// use std::time::Duration;
+///
+/// fn hello(_request: &Request) -> Response {
+///     // configuration of application handling request
+///     let config = ctchi::core::config::current();
+///     Response::html(format!("Hello from {}", config.base_path))
+/// }
+///
+/// fn address(shutdown: &ShutdownHandle) -> SocketAddr {
+///     for _ in 0..100 {
+///         if let Some(address) = shutdown.address() {
+///             return address;
+///         }
+///         thread::sleep(Duration::from_millis(20));
+///     }
+///     panic!("Server isn't running");
+/// }
+///
+/// fn get(address: SocketAddr) -> String {
+///     let mut stream = TcpStream::connect(address).unwrap();
+///     stream.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+///     let mut response = String::new();
+///     stream.read_to_string(&mut response).unwrap();
+///     response
+/// }
+///
+/// let mut servers = Vec::new();
+/// for base_path in ["/srv/first/", "/srv/second/"] {
+///     let mut routes = Routes::new();
+///     routes.add_route(Route { path: "/".to_string(), method: HttpMethod::GET, render_action: hello });
+///
+///     // port 0 lets system choose a free port
+///     let config = Config {
+///         bind_path: "127.0.0.1:0".to_string(),
+///         base_path: base_path.to_string(),
+///         workers: 1,
+///         ..Config::default()
+///     };
+///     let server = Ctchi::builder().routes(routes).config(config).build();
+///     let shutdown = server.shutdown_handle();
+///     servers.push((shutdown, thread::spawn(move || server.start())));
+/// }
+///
+/// assert!(get(address(&servers[0].0)).ends_with("Hello from /srv/first/"));
+/// assert!(get(address(&servers[1].0)).ends_with("Hello from /srv/second/"));
+///
+/// for (shutdown, running) in servers {
+///     shutdown.shutdown();
+///     running.join().unwrap().unwrap();
+/// }
+/// ```
+#[derive(Default)]
+pub struct CtchiBuilder {
+    routes: Option<Routes>,
+    config: Option<Config>,
+}
+
+impl CtchiBuilder {
+    /// Set routes of application.
+    pub fn routes(mut self, routes: Routes) -> CtchiBuilder {
+        self.routes = Some(routes);
+        self
+    }
+
+    /// Set configuration of application, default configuration is used if it isn't set.
+    /// Configuration can't be changed after application is built.
+    pub fn config(mut self, config: Config) -> CtchiBuilder {
+        self.config = Some(config);
+        self
+    }
+
+    pub fn build(self) -> Ctchi {
+        Ctchi {
+            routes: self.routes.unwrap_or_else(Routes::new),
+            config: Some(Arc::new(self.config.unwrap_or_default())),
+            shutdown: ShutdownHandle::new(),
+            queue_depth: QueueDepth::new(),
+        }
+    }
 }
 
 impl Ctchi {
     /// Create new application with specified routes.
     ///
-    /// Configuration gets by `ctchi::core::ctchi::get_configuration()` singleton.
+    /// Configuration gets by `ctchi::core::ctchi::get_configuration()` singleton when application
+    /// starts, use `Ctchi::builder()` to give application its own configuration.
     ///
     /// # Arguments:
     /// * `routes` - list of routes. `ctchi::core::ctchi::Routes`
     ///
     pub fn new(routes: Routes) -> Ctchi {
         Ctchi {
-            routes
+            routes,
+            config: None,
+            shutdown: ShutdownHandle::new(),
+            queue_depth: QueueDepth::new(),
         }
     }
 
+    /// Create builder of application, see `CtchiBuilder`.
+    pub fn builder() -> CtchiBuilder {
+        CtchiBuilder::default()
+    }
+
+    /// Get counter of accepted connections waiting for free worker, for monitoring.
+    pub fn queue_depth(&self) -> QueueDepth {
+        self.queue_depth.clone()
+    }
+
+    /// Get handle which stops the application, see `ShutdownHandle`.
+    /// `SIGTERM` and `SIGINT` stop it the same way.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
     /// Start configured application. Now it will lister for specified ip:port
     /// and respond for request if URI is in routes
     ///
@@ -142,12 +783,13 @@ impl Ctchi {
     /// extern crate ctchi;
     ///
     /// use ctchi::core::app::Ctchi;
+    /// use ctchi::core::http::Response;
     /// use ctchi::core::routes::{Routes, Route};
     ///
     /// use ctchi_codegen::route;
     ///
     /// #[route("/")]
-    /// fn index() -> String {
+    /// fn index() -> Result<String, Response> {
     ///     render!("index.html")
     /// }
     ///
@@ -165,33 +807,397 @@ impl Ctchi {
     /// }
     /// ```
     pub fn start(self) -> std::io::Result<()> {
-        let config_reader = get_configuration();
-        let config = config_reader.inner.lock().unwrap();
-        let bind_path= config.bind_path.to_string();
-        let log_enabled = config.log_enabled;
-        drop(config);
+        let config = match self.config {
+            Some(config) => config,
+            None => {
+                let config_reader = get_configuration();
+                let config = config_reader.inner.lock().unwrap().clone();
+                Arc::new(config)
+            }
+        };
+        let shutdown_timeout = Duration::from_secs(config.shutdown_timeout);
 
-        if log_enabled {
-            logger::init();
+        // log lines of accept loop go to log file of this application
+        let _scope = config::enter(Arc::clone(&config));
+        if config.log_enabled {
+            // logger is global, so the first application sets it up
+            let _ = logger::init_with_path(&config.log_path);
         }
 
         log::info!("Ctchi is running!");
 
-        let listener = TcpListener::bind(bind_path)?;
-        let routes = Arc::new(self.routes);
+        let listener = TcpListener::bind(&config.bind_path)?;
+        let shutdown = self.shutdown;
+        shutdown.set_address(listener.local_addr()?);
+        #[cfg(unix)]
+        let signals = shutdown::on_signals(&shutdown)?;
 
-        let pool = ThreadPool::new(4);
+        let mut routes = self.routes;
+        routes.set_trailing_slash(config.trailing_slash);
+        let handler = Arc::new(RequestHandler {
+            routes,
+            config: Arc::clone(&config),
+            shutdown: shutdown.clone(),
+        });
 
-        for stream in listener.incoming() {
-            let stream = stream.unwrap();
-            let r = routes.clone();
+        let mut pool = ThreadPool::with_queue(config.workers, config.queue_capacity, self.queue_depth);
 
-            pool.execute(|| {
-                let handler = RequestHandler {};
+        // shutdown requested before the address was set can't wake listener up,
+        // so flag is checked before accept too
+        while !shutdown.is_shutdown() {
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(error) => {
+                    log::info!("Can't accept connection: {}", error);
+                    // interrupted call and connection reset before accept concern only one client
+                    if !matches!(error.kind(), ErrorKind::Interrupted | ErrorKind::ConnectionAborted) {
+                        thread::sleep(ACCEPT_RETRY_DELAY);
+                    }
+                    continue;
+                }
+            };
 
-                handler.handle_request(stream, r);
+            // shutdown wakes listener up with its own connection
+            if shutdown.is_shutdown() {
+                break;
+            }
+
+            if pool.is_full() {
+                match config.queue_overflow {
+                    QueueOverflow::Block => {}
+                    QueueOverflow::Reject => {
+                        reject(stream);
+                        continue;
+                    }
+                    QueueOverflow::Drop => {
+                        log::info!("Queue is full, connection is dropped");
+                        continue;
+                    }
+                }
+            }
+
+            let handler = Arc::clone(&handler);
+
+            pool.execute(move || {
+                handler.handle_request(stream);
             });
         }
+
+        log::info!("Ctchi is stopping");
+        drop(listener);
+        #[cfg(unix)]
+        drop(signals);
+
+        if !pool.join(shutdown_timeout) {
+            log::info!("Some requests weren't finished in {} seconds", shutdown_timeout.as_secs());
+        }
+        log::info!("Ctchi is stopped");
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::http::FromRequest;
+    use crate::core::routes::Route;
+    use std::io::Cursor;
+    use std::thread;
+
+    fn echo(request: &Request) -> Response {
+        Response::new(200).with_content(request.body.clone())
+    }
+
+    fn handler(config: Config) -> RequestHandler {
+        let mut routes = Routes::new();
+        routes.add_route(Route { path: "/".to_string(), method: HttpMethod::POST, render_action: echo });
+        RequestHandler {
+            routes,
+            config: Arc::new(config),
+            shutdown: ShutdownHandle::new(),
+        }
+    }
+
+    fn upload(request: &Request) -> Response {
+        match Multipart::from_request(request) {
+            Ok(multipart) => {
+                let file = multipart.file("file").unwrap();
+                let content = format!("{} {} {}", multipart.field("title").unwrap(), file.filename, file.size);
+                Response::new(200).with_content(content.into_bytes())
+            }
+            Err(response) => response,
+        }
+    }
+
+    fn upload_handler(config: Config) -> RequestHandler {
+        let mut handler = handler(config);
+        handler.routes.add_route(Route { path: "/upload".to_string(), method: HttpMethod::POST, render_action: upload });
+        handler
+    }
+
+    fn multipart_body(file: &str) -> String {
+        format!(
+            "--b\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nCat\r\n\
+            --b\r\nContent-Disposition: form-data; name=\"file\"; filename=\"cat.png\"\r\n\r\n{}\r\n--b--\r\n",
+            file
+        )
+    }
+
+    fn chunked(raw: &str, max_body_size: usize) -> Result<Vec<u8>, RequestError> {
+        let mut reader = Cursor::new(raw.as_bytes().to_vec());
+        handler(Config::default()).read_chunked_body(&mut reader, max_body_size, 64, 2)
+    }
+
+    /// Send raw bytes to handler over loopback connection and read everything it answers
+    /// until connection is closed.
+    fn exchange(config: Config, raw: &[u8]) -> String {
+        exchange_with(handler(config), raw)
+    }
+
+    fn exchange_with(handler: RequestHandler, raw: &[u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        let serving = thread::spawn(move || handler.handle_request(server));
+
+        client.write_all(raw).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).unwrap();
+        serving.join().unwrap();
+
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    fn status(response: &str) -> &str {
+        response.lines().next().unwrap_or("")
+    }
+
+    #[test]
+    fn crlf_line_endings() {
+        let mut reader = Cursor::new(b"GET / HTTP/1.1\r\nHost: x\n\r\n".to_vec());
+        assert_eq!(read_crlf_line(&mut reader, 100).unwrap(), "GET / HTTP/1.1");
+        assert_eq!(read_crlf_line(&mut reader, 100).unwrap(), "Host: x");
+        assert_eq!(read_crlf_line(&mut reader, 100).unwrap(), "");
+    }
+
+    #[test]
+    fn crlf_line_errors() {
+        let mut reader = Cursor::new(b"GET / HTTP/1.1".to_vec());
+        assert!(matches!(read_crlf_line(&mut reader, 100), Err(RequestError::BadRequest)));
+
+        let mut reader = Cursor::new(b"GET /\xff HTTP/1.1\r\n".to_vec());
+        assert!(matches!(read_crlf_line(&mut reader, 100), Err(RequestError::BadRequest)));
+
+        let mut reader = Cursor::new(b"GET /very/long/path HTTP/1.1\r\n".to_vec());
+        assert!(matches!(read_crlf_line(&mut reader, 10), Err(RequestError::HeadersTooLarge)));
+    }
+
+    #[test]
+    fn chunked_body_with_extensions_and_trailers() {
+        let body = chunked("5;name=value\r\nHello\r\n7\r\n, world\r\n0\r\nExpires: never\r\n\r\n", 100);
+        assert_eq!(body.unwrap(), b"Hello, world".to_vec());
+    }
+
+    #[test]
+    fn trailer_limits() {
+        assert!(matches!(chunked("0\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n", 100), Err(RequestError::HeadersTooLarge)));
+        let long = format!("0\r\nA: {}\r\nB: {}\r\n\r\n", "a".repeat(30), "b".repeat(30));
+        assert!(matches!(chunked(&long, 100), Err(RequestError::HeadersTooLarge)));
+    }
+
+    #[test]
+    fn trailers_are_not_headers() {
+        // controller would see Authorization if trailers were merged into headers
+        fn authorization(request: &Request) -> Response {
+            Response::new(200).with_content(request.header("authorization").unwrap_or("none").to_string())
+        }
+        let mut routes = Routes::new();
+        routes.add_route(Route { path: "/".to_string(), method: HttpMethod::POST, render_action: authorization });
+        let handler = RequestHandler { routes, config: Arc::new(Config::default()), shutdown: ShutdownHandle::new() };
+
+        let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n\
+            0\r\nAuthorization: Bearer admin\r\n\r\n";
+        let response = exchange_with(handler, raw);
+        assert!(response.ends_with("\r\n\r\nnone"));
+    }
+
+    #[test]
+    fn bad_chunk_framing() {
+        // size isn't hex
+        assert!(matches!(chunked("zz\r\nHello\r\n0\r\n\r\n", 100), Err(RequestError::BadRequest)));
+        assert!(matches!(chunked("+2\r\nok\r\n0\r\n\r\n", 100), Err(RequestError::BadRequest)));
+        // data is longer than size
+        assert!(matches!(chunked("3\r\nHello\r\n0\r\n\r\n", 100), Err(RequestError::BadRequest)));
+        // body ends before the last chunk
+        assert!(matches!(chunked("5\r\nHello\r\n", 100), Err(RequestError::BadRequest)));
+        // trailer without colon
+        assert!(matches!(chunked("0\r\nbroken\r\n\r\n", 100), Err(RequestError::BadRequest)));
+        // chunk bigger than the whole body limit
+        assert!(matches!(chunked("5\r\nHello\r\n0\r\n\r\n", 4), Err(RequestError::PayloadTooLarge)));
+    }
+
+    #[test]
+    fn valid_request() {
+        let response = exchange(Config::default(), b"POST / HTTP/1.1\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok");
+        assert_eq!(status(&response), "HTTP/1.1 200 OK");
+        assert!(response.ends_with("\r\n\r\nok"));
+    }
+
+    #[test]
+    fn bad_request_line() {
+        let response = exchange(Config::default(), b"GET /\r\n\r\n");
+        assert_eq!(status(&response), "HTTP/1.1 400 Bad Request");
+        assert!(response.contains("Connection: close\r\n"));
+    }
+
+    #[test]
+    fn bad_request_target() {
+        for raw in [&b"GET /a\rSet-Cookie:x=1 HTTP/1.1\r\n\r\n"[..], b"GET /a\x01b HTTP/1.1\r\n\r\n", b"GET /<a> HTTP/1.1\r\n\r\n"] {
+            assert_eq!(status(&exchange(Config::default(), raw)), "HTTP/1.1 400 Bad Request");
+        }
+    }
+
+    #[test]
+    fn non_utf8_header() {
+        let response = exchange(Config::default(), b"GET / HTTP/1.1\r\nX-Name: \xff\r\n\r\n");
+        assert_eq!(status(&response), "HTTP/1.1 400 Bad Request");
+    }
+
+    #[test]
+    fn conflicting_content_length() {
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: 2\r\nContent-Length: 3\r\n\r\nok";
+        assert_eq!(status(&exchange(Config::default(), raw)), "HTTP/1.1 400 Bad Request");
+
+        // only digits, proxy could read `+2` differently
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: +2\r\n\r\nok";
+        assert_eq!(status(&exchange(Config::default(), raw)), "HTTP/1.1 400 Bad Request");
+    }
+
+    #[test]
+    fn duplicate_host() {
+        let raw = b"GET / HTTP/1.1\r\nHost: a.example\r\nHost: b.example\r\n\r\n";
+        assert_eq!(status(&exchange(Config::default(), raw)), "HTTP/1.1 400 Bad Request");
+    }
+
+    #[test]
+    fn stalled_body() {
+        let config = Config { body_timeout: 1, ..Config::default() };
+        let response = exchange(config, b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nok");
+        assert_eq!(status(&response), "HTTP/1.1 408 Request Timeout");
+    }
+
+    #[test]
+    fn body_too_large() {
+        let config = Config { max_body_size: 4, ..Config::default() };
+        let response = exchange(config, b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\n");
+        assert_eq!(status(&response), "HTTP/1.1 413 Payload Too Large");
+    }
+
+    #[test]
+    fn headers_too_large() {
+        let config = Config { max_header_size: 64, ..Config::default() };
+        let raw = format!("GET / HTTP/1.1\r\nX-Long: {}\r\n\r\n", "a".repeat(100));
+        assert_eq!(status(&exchange(config, raw.as_bytes())), "HTTP/1.1 431 Request Header Fields Too Large");
+
+        let config = Config { max_header_count: 2, ..Config::default() };
+        let raw = b"GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n";
+        assert_eq!(status(&exchange(config.clone(), raw)), "HTTP/1.1 431 Request Header Fields Too Large");
+
+        // the same name repeated is counted too
+        let raw = b"GET / HTTP/1.1\r\nA: 1\r\nA: 2\r\nA: 3\r\n\r\n";
+        assert_eq!(status(&exchange(config, raw)), "HTTP/1.1 431 Request Header Fields Too Large");
+    }
+
+    #[test]
+    fn unsupported_transfer_coding() {
+        let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n0\r\n\r\n";
+        assert_eq!(status(&exchange(Config::default(), raw)), "HTTP/1.1 501 Not Implemented");
+    }
+
+    #[test]
+    fn transfer_encoding_with_content_length_closes_connection() {
+        // the second request must not be answered
+        let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 2\r\n\r\n2\r\nok\r\n0\r\n\r\n\
+            POST / HTTP/1.1\r\nContent-Length: 0\r\n\r\n";
+        let response = exchange(Config::default(), raw);
+        assert_eq!(status(&response), "HTTP/1.1 200 OK");
+        assert!(response.contains("Connection: close\r\n"));
+        assert_eq!(response.matches("HTTP/1.1").count(), 1);
+    }
+
+    #[test]
+    fn multipart_upload() {
+        let body = multipart_body("0123456789");
+        let raw = format!(
+            "POST /upload HTTP/1.1\r\nConnection: close\r\nContent-Type: multipart/form-data; boundary=b\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let response = exchange_with(upload_handler(Config::default()), raw.as_bytes());
+        assert_eq!(status(&response), "HTTP/1.1 200 OK");
+        assert!(response.ends_with("Cat cat.png 10"));
+
+        // chunks can split the body anywhere
+        let (head, tail) = body.split_at(30);
+        let raw = format!(
+            "POST /upload HTTP/1.1\r\nConnection: close\r\nContent-Type: multipart/form-data; boundary=b\r\n\
+            Transfer-Encoding: chunked\r\n\r\n{:x}\r\n{}\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+            head.len(),
+            head,
+            tail.len(),
+            tail
+        );
+        let response = exchange_with(upload_handler(Config::default()), raw.as_bytes());
+        assert_eq!(status(&response), "HTTP/1.1 200 OK");
+        assert!(response.ends_with("Cat cat.png 10"));
+    }
+
+    #[test]
+    fn multipart_limits() {
+        // multipart bodies aren't limited by `max_body_size`
+        let body = multipart_body(&"a".repeat(100));
+        let raw = format!(
+            "POST /upload HTTP/1.1\r\nConnection: close\r\nContent-Type: multipart/form-data; boundary=b\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let config = Config { max_body_size: 10, ..Config::default() };
+        assert_eq!(status(&exchange_with(upload_handler(config), raw.as_bytes())), "HTTP/1.1 200 OK");
+
+        let config = Config { multipart_max_file_size: 50, ..Config::default() };
+        let response = exchange_with(upload_handler(config), raw.as_bytes());
+        assert_eq!(status(&response), "HTTP/1.1 413 Payload Too Large");
+
+        let config = Config { multipart_max_total_size: 100, ..Config::default() };
+        let response = exchange_with(upload_handler(config), raw.as_bytes());
+        assert_eq!(status(&response), "HTTP/1.1 413 Payload Too Large");
+
+        // total size of chunked body is known only while reading
+        let raw = format!(
+            "POST /upload HTTP/1.1\r\nConnection: close\r\nContent-Type: multipart/form-data; boundary=b\r\n\
+            Transfer-Encoding: chunked\r\n\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+            body.len(),
+            body
+        );
+        let config = Config { multipart_max_total_size: 100, ..Config::default() };
+        let response = exchange_with(upload_handler(config), raw.as_bytes());
+        assert_eq!(status(&response), "HTTP/1.1 413 Payload Too Large");
+    }
+
+    #[test]
+    fn broken_chunked_multipart() {
+        let raw = b"POST /upload HTTP/1.1\r\nConnection: close\r\nContent-Type: multipart/form-data; boundary=b\r\n\
+            Transfer-Encoding: chunked\r\n\r\n+2\r\n--\r\n0\r\n\r\n";
+        let response = exchange_with(upload_handler(Config::default()), raw);
+        assert_eq!(status(&response), "HTTP/1.1 400 Bad Request");
+    }
+
+    #[test]
+    fn static_files_are_read_only() {
+        let raw = b"POST /static/main.css HTTP/1.1\r\nConnection: close\r\nContent-Length: 0\r\n\r\n";
+        let response = exchange(Config::default(), raw);
+        assert_eq!(status(&response), "HTTP/1.1 405 Method Not Allowed");
+        assert!(response.contains("Allow: GET, HEAD\r\n"));
+    }
+}
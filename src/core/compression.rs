@@ -0,0 +1,224 @@
+use std::io::{self, Write};
+
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+
+use super::http::{Request, Response};
+
+/// Content coding supported by Ctchi.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    /// All encodings in order of preference, used when client accepts several
+    /// of them with the same quality.
+    pub const ALL: [Encoding; 3] = [Encoding::Brotli, Encoding::Gzip, Encoding::Deflate];
+
+    /// Name in `Accept-Encoding` and `Content-Encoding` headers.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    /// Extension of precompressed static file, e.g. `main.css.gz`.
+    pub fn extension(&self) -> Option<&'static str> {
+        match self {
+            Encoding::Brotli => Some("br"),
+            Encoding::Gzip => Some("gz"),
+            Encoding::Deflate => None,
+        }
+    }
+
+    /// Compress bytes. `deflate` in HTTP means zlib format, not raw deflate stream.
+    pub fn encode(&self, content: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut result = Vec::new();
+                {
+                    let mut writer = brotli::CompressorWriter::new(&mut result, 4096, 5, 22);
+                    writer.write_all(content)?;
+                }
+                Ok(result)
+            }
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(content)?;
+                encoder.finish()
+            }
+            Encoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(content)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Choose encoding by `Accept-Encoding` header value. Encoding with the highest quality wins,
+/// encodings with the same quality are chosen in order of `available`. `*` stands for any
+/// encoding which isn't listed explicitly, `q=0` forbids encoding.
+///
+/// # Arguments:
+/// * `accept_encoding` - `Accept-Encoding` header value
+/// * `available` - encodings which can be used for response
+///
+/// # Example
+///
+/// ```rust
+/// use ctchi::core::compression::{negotiate, Encoding};
+///
+/// assert_eq!(negotiate("gzip, deflate, br", &Encoding::ALL), Some(Encoding::Brotli));
+/// assert_eq!(negotiate("gzip;q=1.0, br;q=0.5", &Encoding::ALL), Some(Encoding::Gzip));
+/// assert_eq!(negotiate("br", &[Encoding::Gzip]), None);
+/// assert_eq!(negotiate("*;q=0.5, br;q=0", &Encoding::ALL), Some(Encoding::Gzip));
+/// assert_eq!(negotiate("identity", &Encoding::ALL), None);
+/// ```
+pub fn negotiate(accept_encoding: &str, available: &[Encoding]) -> Option<Encoding> {
+    let mut qualities = Vec::new();
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or("").trim().to_lowercase();
+        if name.is_empty() {
+            continue;
+        }
+
+        let quality = parts
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .next()
+            .map(|q| q.trim().parse::<f32>().unwrap_or(0.0))
+            .unwrap_or(1.0);
+        qualities.push((name, quality));
+    }
+
+    let quality_of = |encoding: &Encoding| {
+        let find = |name: &str| qualities.iter().find(|(n, _)| n == name).map(|(_, q)| *q);
+        // old clients send x-gzip
+        let explicit = match encoding {
+            Encoding::Gzip => find("gzip").or_else(|| find("x-gzip")),
+            _ => find(encoding.as_str()),
+        };
+        explicit.or_else(|| find("*")).unwrap_or(0.0)
+    };
+
+    let mut best: Option<(Encoding, f32)> = None;
+    for encoding in available {
+        let quality = quality_of(encoding);
+        if quality > 0.0 && best.is_none_or(|(_, q)| quality > q) {
+            best = Some((*encoding, quality));
+        }
+    }
+
+    best.map(|(encoding, _)| encoding)
+}
+
+/// Check if response with this `Content-Type` is worth compressing. Images, fonts
+/// and archives are usually compressed already.
+pub fn is_compressible(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or("").trim().to_lowercase();
+    mime.starts_with("text/")
+        || mime.ends_with("+json")
+        || mime.ends_with("+xml")
+        || mime == "application/json"
+        || mime == "application/javascript"
+        || mime == "application/xml"
+        || mime == "application/wasm"
+}
+
+/// Add value to `Vary` header, keeping values which are already there.
+pub fn add_vary(response: &mut Response, value: &str) {
+    let vary = match response.header("vary") {
+        Some(vary) if vary.split(',').any(|v| v.trim() == "*" || v.trim().eq_ignore_ascii_case(value)) => return,
+        Some(vary) => format!("{}, {}", vary, value),
+        None => value.to_string(),
+    };
+    response.set_header("Vary", &vary);
+}
+
+/// Compress response body if client accepts it. Only successful responses with text-like
+/// `Content-Type` and body not smaller than `threshold` are compressed. Responses which are
+/// already encoded and partial responses are sent as is.
+///
+/// Every response which could be compressed gets `Vary: Accept-Encoding`, so caches don't
+/// give compressed body to clients which can't read it. Compressed body is built on every
+/// request, byte ranges of it aren't served, so `Accept-Ranges` is removed.
+///
+/// # Arguments:
+/// * `request` - request with `Accept-Encoding` header
+/// * `response` - response from controller or static files
+/// * `threshold` - minimal body size in bytes, smaller bodies don't get smaller after compression
+///
+/// # Example
+///
+/// ```rust
+/// use std::collections::HashMap;
+/// use ctchi::core::compression::compress;
+/// use ctchi::core::http::{HttpMethod, Request, Response};
+///
+/// let mut headers = HashMap::new();
+/// headers.insert("accept-encoding".to_string(), "gzip".to_string());
+/// let request = Request {
+///     method: HttpMethod::GET,
+///     url: "/".to_string(),
+///     version: "HTTP/1.1".to_string(),
+///     query_string: String::new(),
+///     headers,
+///     body: Vec::new(),
+///     params: HashMap::new(),
+/// };
+///
+/// let page = "<p>Hello</p>".repeat(100);
+/// let response = compress(&request, Response::html(page.clone()), 1024);
+/// assert_eq!(response.header("content-encoding"), Some("gzip"));
+/// assert_eq!(response.header("vary"), Some("Accept-Encoding"));
+/// assert!(response.content.len() < page.len());
+///
+/// let ranged = Response::html(page.clone()).with_header("Accept-Ranges", "bytes");
+/// let response = compress(&request, ranged, 1024);
+/// assert_eq!(response.header("content-encoding"), Some("gzip"));
+/// assert_eq!(response.header("accept-ranges"), None);
+///
+/// let response = compress(&request, Response::html("<p>Hello</p>".to_string()), 1024);
+/// assert_eq!(response.header("content-encoding"), None);
+/// ```
+pub fn compress(request: &Request, mut response: Response, threshold: usize) -> Response {
+    // body from file is streamed, it isn't compressed on the fly
+    let compressible = response.code == 200
+        && response.file.is_none()
+        && response.header("content-encoding").is_none()
+        && response.header("content-type").is_some_and(is_compressible);
+    if !compressible || response.content.len() < threshold {
+        return response;
+    }
+
+    add_vary(&mut response, "Accept-Encoding");
+
+    let encoding = match request.header("accept-encoding").and_then(|a| negotiate(a, &Encoding::ALL)) {
+        Some(encoding) => encoding,
+        None => return response,
+    };
+
+    match encoding.encode(&response.content) {
+        Ok(content) => {
+            // compressed body is another representation, so strong validator can't be the same
+            if let Some(etag) = response.header("etag").map(|e| e.to_string()) {
+                if !etag.starts_with("W/") {
+                    response.set_header("ETag", &format!("W/{}", etag));
+                }
+            }
+            response.set_header("Content-Encoding", encoding.as_str());
+            response.headers.retain(|(name, _)| !name.eq_ignore_ascii_case("accept-ranges"));
+            response.with_content(content)
+        }
+        Err(error) => {
+            log::info!("Can't compress response: {}", error);
+            response
+        }
+    }
+}
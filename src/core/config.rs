@@ -1,31 +1,115 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::env::current_dir;
 use std::fs;
-use std::sync::{Arc, Mutex, Once};
-use core::mem;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
 
-#[derive(Debug)]
+use super::routes::TrailingSlash;
+use super::thread_pool::QueueOverflow;
+
+#[derive(Clone, Debug)]
 pub struct Config {
     pub bind_path: String,
     pub base_path: String,
     pub static_uri_pref: String,
     pub log_path: String,
     pub log_enabled: bool,
+    pub max_body_size: usize,
+    pub multipart_max_file_size: usize,
+    pub multipart_max_total_size: usize,
+    pub multipart_memory_size: usize,
+    /// Extension (without dot) to MIME type map for static files, overrides built-in types.
+    pub mime_types: HashMap<String, String>,
+    /// `Cache-Control` values for static files by URL path prefix (starts with `/`)
+    /// or by file extension, see `static_files::cache_control`.
+    pub cache_control: Vec<(String, String)>,
+    pub trailing_slash: TrailingSlash,
+    /// Responses smaller than that (in bytes) aren't compressed.
+    pub compression_threshold: usize,
+    /// Idle connection is closed after that many seconds without new request, `0` turns keep-alive off.
+    pub keep_alive_timeout: u64,
+    /// Connection is closed after that many requests, `1` turns keep-alive off.
+    pub keep_alive_max_requests: usize,
+    /// How many seconds requests in progress have to finish after shutdown.
+    pub shutdown_timeout: u64,
+    /// Number of threads handling connections.
+    pub workers: usize,
+    /// Maximum number of accepted connections waiting for free worker.
+    pub queue_capacity: usize,
+    pub queue_overflow: QueueOverflow,
+    /// Seconds to receive request line and headers after the first byte, `0` is no limit.
+    pub header_timeout: u64,
+    /// Seconds to receive request body, `0` is no limit.
+    pub body_timeout: u64,
+    /// Seconds to send response, `0` is no limit.
+    pub write_timeout: u64,
+    /// Maximum size of request line and headers in bytes.
+    pub max_header_size: usize,
+    pub max_header_count: usize,
+    /// Maximum number of bytes sent for `Range` request, longer ranges are cut.
+    pub max_range_size: u64,
+}
+
+impl Default for Config {
+    /// Default configuration, configuration file isn't read.
+    fn default() -> Config {
+        Config::parse("")
+    }
 }
 
 impl Config {
+    /// Read configuration from `/etc/ctchi/conf.txt`, missing file gives default configuration.
     pub fn new() -> Config {
-        Config::parse_config("/etc/ctchi/conf.txt")
+        Config::from_file("/etc/ctchi/conf.txt")
+    }
+
+    /// Read configuration from file, missing file gives default configuration.
+    pub fn from_file(path: &str) -> Config {
+        let config_content = fs::read_to_string(path).unwrap_or_default();
+        Config::parse(&config_content)
     }
 
-    fn parse_config(path: &str) -> Config {
+    /// Parse configuration from `name=value` lines, options which aren't set keep
+    /// default values.
+    ///
+    /// # Panics
+    ///
+    /// Panics if some value can't be parsed.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use ctchi::core::config::Config;
+    ///
+    /// let config = Config::parse("bind_path=127.0.0.1:3000\nworkers=8");
+    /// assert_eq!(config.bind_path, "127.0.0.1:3000");
+    /// assert_eq!(config.workers, 8);
+    /// assert_eq!(config.max_body_size, Config::default().max_body_size);
+    /// ```
+    pub fn parse(config_content: &str) -> Config {
         let mut bind_path = "127.0.0.1:8080";
         let mut log_path = "/var/log/ctchi/server.log";
         let mut log_enabled = false;
-
-        let config_content = match fs::read_to_string(path) {
-            Ok(content) => content,
-            Err(_) => String::new()
-        };
+        let mut max_body_size = 10 * 1024 * 1024;
+        let mut multipart_max_file_size = 5 * 1024 * 1024;
+        let mut multipart_max_total_size = 10 * 1024 * 1024;
+        let mut multipart_memory_size = 256 * 1024;
+        let mut mime_types = HashMap::new();
+        let mut cache_control = Vec::new();
+        let mut trailing_slash = TrailingSlash::Lenient;
+        let mut compression_threshold = 1024;
+        let mut keep_alive_timeout = 5;
+        let mut keep_alive_max_requests = 100;
+        let mut shutdown_timeout = 30;
+        let mut workers = 4;
+        let mut queue_capacity = 256;
+        let mut queue_overflow = QueueOverflow::Block;
+        let mut header_timeout = 10;
+        let mut body_timeout = 30;
+        let mut write_timeout = 30;
+        let mut max_header_size = 8 * 1024;
+        let mut max_header_count = 100;
+        let mut max_range_size = 8 * 1024 * 1024;
 
         let mut templates_dir = format!(
             "{}{}",
@@ -35,7 +119,8 @@ impl Config {
 
         let lines = config_content.split("\n").collect::<Vec<&str>>();
         for l in lines {
-            let cols = l.split("=").collect::<Vec<&str>>();
+            // only the first `=` separates name and value
+            let cols = l.splitn(2, "=").collect::<Vec<&str>>();
             if cols[0] == "bind_path" {
                 bind_path = cols[1];
             }
@@ -52,6 +137,88 @@ impl Config {
                     _ => panic!("Wrong log value '{}'", cols[1])
                 }
             }
+            if cols[0] == "max_body_size" {
+                max_body_size = cols[1].parse::<usize>()
+                    .unwrap_or_else(|_| panic!("Wrong max body size value '{}'", cols[1]));
+            }
+            if cols[0] == "multipart_max_file_size" {
+                multipart_max_file_size = cols[1].parse::<usize>()
+                    .unwrap_or_else(|_| panic!("Wrong multipart max file size value '{}'", cols[1]));
+            }
+            if cols[0] == "multipart_max_total_size" {
+                multipart_max_total_size = cols[1].parse::<usize>()
+                    .unwrap_or_else(|_| panic!("Wrong multipart max total size value '{}'", cols[1]));
+            }
+            if cols[0] == "multipart_memory_size" {
+                multipart_memory_size = cols[1].parse::<usize>()
+                    .unwrap_or_else(|_| panic!("Wrong multipart memory size value '{}'", cols[1]));
+            }
+            if cols[0] == "trailing_slash" {
+                trailing_slash = TrailingSlash::parse(cols[1])
+                    .unwrap_or_else(|| panic!("Wrong trailing slash value '{}'", cols[1]));
+            }
+            if cols[0] == "compression_threshold" {
+                compression_threshold = cols[1].parse::<usize>()
+                    .unwrap_or_else(|_| panic!("Wrong compression threshold value '{}'", cols[1]));
+            }
+            if cols[0] == "keep_alive_timeout" {
+                keep_alive_timeout = cols[1].parse::<u64>()
+                    .unwrap_or_else(|_| panic!("Wrong keep alive timeout value '{}'", cols[1]));
+            }
+            if cols[0] == "keep_alive_max_requests" {
+                keep_alive_max_requests = cols[1].parse::<usize>()
+                    .unwrap_or_else(|_| panic!("Wrong keep alive max requests value '{}'", cols[1]));
+            }
+            if cols[0] == "shutdown_timeout" {
+                shutdown_timeout = cols[1].parse::<u64>()
+                    .unwrap_or_else(|_| panic!("Wrong shutdown timeout value '{}'", cols[1]));
+            }
+            if cols[0] == "workers" {
+                workers = cols[1].parse::<usize>().ok()
+                    .filter(|&workers| workers > 0)
+                    .unwrap_or_else(|| panic!("Wrong workers value '{}'", cols[1]));
+            }
+            if cols[0] == "queue_capacity" {
+                queue_capacity = cols[1].parse::<usize>().ok()
+                    .filter(|&capacity| capacity > 0)
+                    .unwrap_or_else(|| panic!("Wrong queue capacity value '{}'", cols[1]));
+            }
+            if cols[0] == "queue_overflow" {
+                queue_overflow = QueueOverflow::parse(cols[1])
+                    .unwrap_or_else(|| panic!("Wrong queue overflow value '{}'", cols[1]));
+            }
+            if cols[0] == "header_timeout" {
+                header_timeout = cols[1].parse::<u64>()
+                    .unwrap_or_else(|_| panic!("Wrong header timeout value '{}'", cols[1]));
+            }
+            if cols[0] == "body_timeout" {
+                body_timeout = cols[1].parse::<u64>()
+                    .unwrap_or_else(|_| panic!("Wrong body timeout value '{}'", cols[1]));
+            }
+            if cols[0] == "write_timeout" {
+                write_timeout = cols[1].parse::<u64>()
+                    .unwrap_or_else(|_| panic!("Wrong write timeout value '{}'", cols[1]));
+            }
+            if cols[0] == "max_header_size" {
+                max_header_size = cols[1].parse::<usize>()
+                    .unwrap_or_else(|_| panic!("Wrong max header size value '{}'", cols[1]));
+            }
+            if cols[0] == "max_header_count" {
+                max_header_count = cols[1].parse::<usize>()
+                    .unwrap_or_else(|_| panic!("Wrong max header count value '{}'", cols[1]));
+            }
+            if cols[0] == "max_range_size" {
+                max_range_size = cols[1].parse::<u64>()
+                    .unwrap_or_else(|_| panic!("Wrong max range size value '{}'", cols[1]));
+            }
+            // mime.md=text/markdown
+            if let Some(extension) = cols[0].strip_prefix("mime.") {
+                mime_types.insert(extension.to_lowercase(), cols[1].trim().to_string());
+            }
+            // cache_control./static/img/=max-age=86400 or cache_control.css=no-cache
+            if let Some(selector) = cols[0].strip_prefix("cache_control.") {
+                cache_control.push((selector.to_string(), cols[1].trim().to_string()));
+            }
         }
 
         Config {
@@ -60,6 +227,26 @@ impl Config {
             static_uri_pref: "/static/".to_string(),
             log_path: log_path.to_string(),
             log_enabled,
+            max_body_size,
+            multipart_max_file_size,
+            multipart_max_total_size,
+            multipart_memory_size,
+            mime_types,
+            cache_control,
+            trailing_slash,
+            compression_threshold,
+            keep_alive_timeout,
+            keep_alive_max_requests,
+            shutdown_timeout,
+            workers,
+            queue_capacity,
+            queue_overflow,
+            header_timeout,
+            body_timeout,
+            write_timeout,
+            max_header_size,
+            max_header_count,
+            max_range_size,
         }
     }
 }
@@ -71,23 +258,54 @@ pub struct ConfigReader {
     pub inner: Arc<Mutex<Config>>,
 }
 
+/// Global configuration read from `/etc/ctchi/conf.txt`.
+///
+/// It's kept for compatibility: applications built with `Ctchi::builder()` have their own
+/// configuration, `Ctchi::new()` takes a snapshot of this one when it starts.
+/// Use `current()` to get configuration of application handling request.
 pub fn get_configuration() -> ConfigReader {
-    // Initialize it to a null value
-    static mut SINGLETON: *const ConfigReader = 0 as *const ConfigReader;
-    static ONCE: Once = Once::new();
-
-    unsafe {
-        ONCE.call_once(|| {
-            // Make it
-            let singleton = ConfigReader {
-                inner: Arc::new(Mutex::new(Config::new())),
-            };
-
-            // Put it in the heap so it can outlive this call
-            SINGLETON = mem::transmute(Box::new(singleton));
-        });
-
-        // Now we give out a copy of the data that is safe to use concurrently.
-        (*SINGLETON).clone()
+    static SINGLETON: OnceLock<ConfigReader> = OnceLock::new();
+
+    SINGLETON.get_or_init(|| ConfigReader {
+        inner: Arc::new(Mutex::new(Config::new())),
+    }).clone()
+}
+
+thread_local! {
+    /// Configuration of application handling request in this thread.
+    static CURRENT: RefCell<Option<Arc<Config>>> = const { RefCell::new(None) };
+}
+
+/// Get configuration of application handling current request, e.g. in controller.
+/// Outside of request handling it's a snapshot of global configuration.
+pub fn current() -> Arc<Config> {
+    entered().unwrap_or_else(|| {
+        let config_reader = get_configuration();
+        let config = config_reader.inner.lock().unwrap_or_else(PoisonError::into_inner).clone();
+        Arc::new(config)
+    })
+}
+
+/// Configuration entered with `enter` on this thread, it's cheap unlike `current`
+/// outside of request handling.
+pub(crate) fn entered() -> Option<Arc<Config>> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Make `config` current for this thread until returned guard is dropped.
+pub(crate) fn enter(config: Arc<Config>) -> ConfigScope {
+    let previous = CURRENT.with(|current| current.replace(Some(config)));
+    ConfigScope { previous }
+}
+
+/// Restores previous current configuration on drop.
+pub(crate) struct ConfigScope {
+    previous: Option<Arc<Config>>,
+}
+
+impl Drop for ConfigScope {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
     }
 }
\ No newline at end of file
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use super::http::{FromRequest, Request, Response};
+
+/// Submitted form fields, every field can have several values.
+pub type FormFields = HashMap<String, Vec<String>>;
+
+/// Why form can't be converted into user struct.
+#[derive(Debug, PartialEq)]
+pub enum FormError {
+    /// Required field is absent.
+    Missing(String),
+    /// Field value can't be parsed into field type.
+    Invalid(String),
+}
+
+impl fmt::Display for FormError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormError::Missing(name) => write!(f, "Field '{}' is required", name),
+            FormError::Invalid(name) => write!(f, "Field '{}' has wrong value", name),
+        }
+    }
+}
+
+/// Struct which can be built from submitted form. Usually it's derived
+/// with `#[derive(FromForm)]` from `ctchi_codegen`.
+pub trait FromForm: Sized {
+    fn from_form(fields: &FormFields) -> Result<Self, FormError>;
+}
+
+/// Controller argument with parsed `application/x-www-form-urlencoded` body.
+///
+/// Request with another content type gets `415 Unsupported Media Type`,
+/// form which can't be converted into `T` gets `400 Bad Request`.
+pub struct Form<T>(pub T);
+
+impl<T: FromForm> FromRequest for Form<T> {
+    fn from_request(request: &Request) -> Result<Form<T>, Response> {
+        let fields = request.form().ok_or_else(|| Response::new(415))?;
+
+        T::from_form(&fields).map(Form).map_err(|error| {
+            Response::new(400)
+                .with_header("Content-Type", "text/plain; charset=utf-8")
+                .with_content(error.to_string())
+        })
+    }
+}
+
+/// Get the first value of required field.
+///
+/// # Example
+///
+/// ```rust
+/// use ctchi::core::form::{required, FormError};
+/// use ctchi::core::url::parse_query;
+///
+/// let fields = parse_query("age=42&name=");
+/// assert_eq!(required::<u32>(&fields, "age"), Ok(42));
+/// assert_eq!(required::<String>(&fields, "name"), Ok(String::new()));
+/// assert_eq!(required::<u32>(&fields, "height"), Err(FormError::Missing("height".to_string())));
+/// ```
+pub fn required<T: FromStr>(fields: &FormFields, name: &str) -> Result<T, FormError> {
+    optional(fields, name)?.ok_or_else(|| FormError::Missing(name.to_string()))
+}
+
+/// Get the first value of optional field, `None` if field is absent.
+pub fn optional<T: FromStr>(fields: &FormFields, name: &str) -> Result<Option<T>, FormError> {
+    match fields.get(name).and_then(|values| values.first()) {
+        Some(value) => value.parse::<T>()
+            .map(Some)
+            .map_err(|_| FormError::Invalid(name.to_string())),
+        None => Ok(None),
+    }
+}
+
+/// Get all values of field, e.g. checked options of multiple select.
+pub fn multiple<T: FromStr>(fields: &FormFields, name: &str) -> Result<Vec<T>, FormError> {
+    fields.get(name)
+        .map(|values| values.iter().map(|v| v.parse::<T>()).collect::<Result<Vec<T>, _>>())
+        .unwrap_or_else(|| Ok(Vec::new()))
+        .map_err(|_| FormError::Invalid(name.to_string()))
+}
+
+/// Get checkbox state. Browsers don't send unchecked checkboxes and send `on`
+/// for checked ones without value, so only explicit false values are false.
+///
+/// # Example
+///
+/// ```rust
+/// use ctchi::core::form::flag;
+/// use ctchi::core::url::parse_query;
+///
+/// let fields = parse_query("subscribe=on&agree=false");
+/// assert!(flag(&fields, "subscribe"));
+/// assert!(!flag(&fields, "agree"));
+/// assert!(!flag(&fields, "remember"));
+/// ```
+pub fn flag(fields: &FormFields, name: &str) -> bool {
+    match fields.get(name).and_then(|values| values.first()) {
+        Some(value) => !matches!(value.to_lowercase().as_str(), "false" | "off" | "0"),
+        None => false,
+    }
+}
@@ -1,8 +1,13 @@
 use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+
+use super::url::parse_query;
 
 /// Represent HTTP method + unknown value in case we are missing something in enum or
 /// client send us wrong request.
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
 pub enum HttpMethod {
     GET,
     POST,
@@ -46,16 +51,489 @@ impl HttpMethod {
             _ => HttpMethod::UNKNOWN,
         }
     }
+
+    /// Get method name as it is written in requests.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use ctchi::core::http::HttpMethod;
+    /// assert_eq!(HttpMethod::POST.as_str(), "POST");
+    /// ```
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HttpMethod::GET => "GET",
+            HttpMethod::POST => "POST",
+            HttpMethod::PUT => "PUT",
+            HttpMethod::DELETE => "DELETE",
+            HttpMethod::OPTIONS => "OPTIONS",
+            HttpMethod::HEAD => "HEAD",
+            HttpMethod::CONNECT => "CONNECT",
+            HttpMethod::TRACE => "TRACE",
+            HttpMethod::PATCH => "PATCH",
+            HttpMethod::UNKNOWN => "UNKNOWN",
+        }
+    }
 }
 
+#[derive(Clone, Debug)]
 pub struct Request {
     pub method: HttpMethod,
+    /// Request path without query string, still percent encoded.
     pub url: String,
+    /// Protocol version from request line, e.g. `HTTP/1.1`.
+    pub version: String,
+    /// Raw query string, part of URI after `?`.
+    pub query_string: String,
+    /// Header names are stored in lower case, use `Request::header` for lookups.
     pub headers: HashMap<String, String>,
-    pub body: String,
+    /// Raw request body, exactly as many bytes as client announced.
+    /// `multipart/form-data` body is parsed while reading and is available only through `Multipart`.
+    pub body: Vec<u8>,
+    /// Percent decoded values of route path parameters, filled by router.
+    pub params: HashMap<String, String>,
+}
+
+impl Request {
+    /// Get header value by case insensitive name.
+    ///
+    /// # Arguments:
+    /// * `name` - header name, e.g. `Content-Type`
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_lowercase()).map(|v| v.as_str())
+    }
+
+    /// Check if client wants to keep connection open after response. HTTP/1.1 connections
+    /// are persistent unless client sends `Connection: close`, HTTP/1.0 ones are closed
+    /// unless client sends `Connection: keep-alive`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use ctchi::core::http::{HttpMethod, Request};
+    /// use std::collections::HashMap;
+    ///
+    /// let mut request = Request {
+    ///     method: HttpMethod::GET,
+    ///     url: "/".to_string(),
+    ///     version: "HTTP/1.1".to_string(),
+    ///     query_string: String::new(),
+    ///     headers: HashMap::new(),
+    ///     body: Vec::new(),
+    ///     params: HashMap::new(),
+    /// };
+    /// assert!(request.keep_alive());
+    ///
+    /// request.headers.insert("connection".to_string(), "Close".to_string());
+    /// assert!(!request.keep_alive());
+    ///
+    /// request.version = "HTTP/1.0".to_string();
+    /// request.headers.insert("connection".to_string(), "keep-alive".to_string());
+    /// assert!(request.keep_alive());
+    /// ```
+    pub fn keep_alive(&self) -> bool {
+        let has_option = |option: &str| {
+            self.header("connection")
+                .map(|value| value.split(',').any(|o| o.trim().eq_ignore_ascii_case(option)))
+                .unwrap_or(false)
+        };
+
+        if self.version == "HTTP/1.0" {
+            has_option("keep-alive")
+        } else {
+            !has_option("close")
+        }
+    }
+
+    /// Parse query string into multimap of percent decoded keys and values.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use ctchi::core::http::{HttpMethod, Request};
+    /// use std::collections::HashMap;
+    ///
+    /// let request = Request {
+    ///     method: HttpMethod::GET,
+    ///     url: "/search".to_string(),
+    ///     version: "HTTP/1.1".to_string(),
+    ///     query_string: "q=hello%20world&page=2".to_string(),
+    ///     headers: HashMap::new(),
+    ///     body: Vec::new(),
+    ///     params: HashMap::new(),
+    /// };
+    /// assert_eq!(request.query()["q"], vec!["hello world"]);
+    /// assert_eq!(request.query()["page"], vec!["2"]);
+    /// ```
+    pub fn query(&self) -> HashMap<String, Vec<String>> {
+        parse_query(&self.query_string)
+    }
+
+    /// Get value of route path parameter, e.g. `id` for `/blog/{id}/` route.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(|v| v.as_str())
+    }
+
+    /// Get media type of the body in lower case and without parameters,
+    /// `text/html` for `Content-Type: text/html; charset=UTF-8`.
+    pub fn media_type(&self) -> Option<String> {
+        self.header("content-type")
+            .map(|value| value.split(';').next().unwrap_or("").trim().to_lowercase())
+    }
+
+    /// Parse `application/x-www-form-urlencoded` body into multimap of decoded keys and values.
+    /// Returns `None` if request has another content type.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use ctchi::core::http::{HttpMethod, Request};
+    /// use std::collections::HashMap;
+    ///
+    /// let mut headers = HashMap::new();
+    /// headers.insert("content-type".to_string(), "application/x-www-form-urlencoded".to_string());
+    /// let request = Request {
+    ///     method: HttpMethod::POST,
+    ///     url: "/contact".to_string(),
+    ///     version: "HTTP/1.1".to_string(),
+    ///     query_string: String::new(),
+    ///     headers,
+    ///     body: b"name=Leonid+T&message=Hi%21".to_vec(),
+    ///     params: HashMap::new(),
+    /// };
+    /// let form = request.form().unwrap();
+    /// assert_eq!(form["name"], vec!["Leonid T"]);
+    /// assert_eq!(form["message"], vec!["Hi!"]);
+    /// ```
+    pub fn form(&self) -> Option<HashMap<String, Vec<String>>> {
+        if self.media_type()? != "application/x-www-form-urlencoded" {
+            return None;
+        }
+
+        Some(parse_query(&String::from_utf8_lossy(&self.body)))
+    }
 }
 
+/// HTTP response which is sent back to client.
+///
+/// `Content-Length` is always calculated from body during serialization,
+/// other headers are written as is in the order they were added.
+#[derive(Clone, Debug)]
 pub struct Response {
-    pub code: String,
-    pub content: String,
-}
\ No newline at end of file
+    pub code: u16,
+    /// Header names and values, the same name can be repeated, e.g. `Set-Cookie`.
+    pub headers: Vec<(String, String)>,
+    pub content: Vec<u8>,
+    /// Body sent from file after `content`, see `with_file`.
+    pub file: Option<FileBody>,
+}
+
+/// Part of open file sent as response body. It's copied to connection while response
+/// is written, so big files aren't read into memory.
+#[derive(Clone, Debug)]
+pub struct FileBody {
+    pub file: Arc<File>,
+    /// Offset of the first byte to send.
+    pub start: u64,
+    pub len: u64,
+}
+
+impl Response {
+    /// Create empty response with specified status code.
+    ///
+    /// # Arguments:
+    /// * `code` - HTTP status code, e.g. `404`
+    pub fn new(code: u16) -> Response {
+        Response {
+            code,
+            headers: Vec::new(),
+            content: Vec::new(),
+            file: None,
+        }
+    }
+
+    /// Create `200 OK` response with html content.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use ctchi::core::http::Response;
+    /// let response = Response::html("<p>Hello</p>".to_string());
+    /// assert_eq!(response.code, 200);
+    /// assert_eq!(response.header("content-type"), Some("text/html; charset=utf-8"));
+    /// ```
+    pub fn html(content: String) -> Response {
+        Response::new(200)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_content(content)
+    }
+
+    /// Set header value and return response back, so calls can be chained.
+    /// Header with the same name in any case is replaced.
+    pub fn with_header(mut self, name: &str, value: &str) -> Response {
+        self.set_header(name, value);
+        self
+    }
+
+    /// Replace response body.
+    pub fn with_content<T: Into<Vec<u8>>>(mut self, content: T) -> Response {
+        self.content = content.into();
+        self.file = None;
+        self
+    }
+
+    /// Replace response body with part of file, it's read only when response is written.
+    ///
+    /// # Arguments:
+    /// * `file` - open file
+    /// * `start` - offset of the first byte to send
+    /// * `len` - number of bytes to send
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::fs::{self, File};
+    /// use ctchi::core::http::Response;
+    ///
+    /// let path = std::env::temp_dir().join("ctchi-with-file.txt");
+    /// fs::write(&path, "Hello, world").unwrap();
+    /// let response = Response::new(200).with_file(File::open(&path).unwrap(), 7, 5);
+    /// assert_eq!(response.content_length(), 5);
+    ///
+    /// let mut written = Vec::new();
+    /// response.write_to(&mut written, true).unwrap();
+    /// assert_eq!(written, b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nworld".to_vec());
+    ///
+    /// // answer to HEAD request has the same headers
+    /// let mut written = Vec::new();
+    /// response.write_to(&mut written, false).unwrap();
+    /// assert_eq!(written, b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n".to_vec());
+    /// fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn with_file(mut self, file: File, start: u64, len: u64) -> Response {
+        self.content = Vec::new();
+        self.file = Some(FileBody { file: Arc::new(file), start, len });
+        self
+    }
+
+    /// Size of body in bytes, including part of file.
+    pub fn content_length(&self) -> u64 {
+        self.content.len() as u64 + self.file.as_ref().map(|body| body.len).unwrap_or(0)
+    }
+
+    /// Set header value, header with the same name in any case is replaced.
+    /// Replaced header keeps its place, its other copies are removed.
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        let mut found = false;
+        self.headers.retain_mut(|(k, v)| {
+            if !k.eq_ignore_ascii_case(name) {
+                return true;
+            }
+            if found {
+                return false;
+            }
+            found = true;
+            *v = value.to_string();
+            true
+        });
+
+        if !found {
+            self.headers.push((name.to_string(), value.to_string()));
+        }
+    }
+
+    /// Add header without touching headers with the same name, e.g. to set several cookies.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use ctchi::core::http::Response;
+    ///
+    /// let mut response = Response::new(200);
+    /// response.append_header("Set-Cookie", "session=abc; HttpOnly");
+    /// response.append_header("Set-Cookie", "theme=dark");
+    /// assert_eq!(response.header("set-cookie"), Some("session=abc; HttpOnly"));
+    /// assert_eq!(
+    ///     response.to_bytes(),
+    ///     b"HTTP/1.1 200 OK\r\nSet-Cookie: session=abc; HttpOnly\r\nSet-Cookie: theme=dark\r\nContent-Length: 0\r\n\r\n".to_vec()
+    /// );
+    ///
+    /// response.set_header("set-cookie", "session=");
+    /// assert_eq!(response.headers, vec![("Set-Cookie".to_string(), "session=".to_string())]);
+    /// ```
+    pub fn append_header(&mut self, name: &str, value: &str) {
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+
+    /// Get value of the first header with case insensitive name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Serialize response in HTTP/1.1 wire format: status line, headers, empty line and body.
+    /// `Content-Length` isn't written for `1xx`, `204` and `304` responses.
+    /// Body from file isn't read here, `write_to` sends it.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use ctchi::core::http::Response;
+    /// let response = Response::new(404).with_content("Not here");
+    /// assert_eq!(
+    ///     response.to_bytes(),
+    ///     b"HTTP/1.1 404 Not Found\r\nContent-Length: 8\r\n\r\nNot here".to_vec()
+    /// );
+    /// ```
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.code, status_reason(self.code));
+        for (name, value) in &self.headers {
+            if name.eq_ignore_ascii_case("content-length") {
+                continue;
+            }
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        // responses without body must not have Content-Length of empty body
+        if !(self.code < 200 || self.code == 204 || self.code == 304) {
+            head.push_str(&format!("Content-Length: {}\r\n", self.content_length()));
+        }
+        head.push_str("\r\n");
+
+        let mut result = head.into_bytes();
+        result.extend_from_slice(&self.content);
+        result
+    }
+
+    /// Write response to connection, body from file is copied without reading it into memory.
+    ///
+    /// # Arguments:
+    /// * `writer` - connection
+    /// * `with_body` - `false` for answer to `HEAD` request, it has the same headers, but no body
+    pub fn write_to<W: Write>(&self, writer: &mut W, with_body: bool) -> io::Result<()> {
+        let mut bytes = self.to_bytes();
+        if !with_body {
+            bytes.truncate(bytes.len() - self.content.len());
+            return writer.write_all(&bytes);
+        }
+        writer.write_all(&bytes)?;
+
+        if let Some(body) = &self.file {
+            let mut file = &*body.file;
+            file.seek(SeekFrom::Start(body.start))?;
+            // file can be truncated after `Content-Length` was sent, connection can't be reused then
+            if io::copy(&mut file.take(body.len), writer)? < body.len {
+                return Err(io::Error::new(ErrorKind::UnexpectedEof, "file is shorter than response body"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Anything that can be built from request and taken by controller as argument,
+/// e.g. `Form<T>`. Error is sent to client as is.
+///
+/// Route macros recognize `Form`, `Multipart` and `Json` by name, arguments of other types
+/// implementing it should be marked with `#[from_request]`.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// struct Session(String);
+///
+/// impl FromRequest for Session {
+///     fn from_request(request: &Request) -> Result<Session, Response> {
+///         match request.header("cookie") {
+///             Some(cookie) => Ok(Session(cookie.to_string())),
+///             None => Err(Response::new(401)),
+///         }
+///     }
+/// }
+///
+/// #[route("/account/")]
+/// fn account(#[from_request] session: Session) -> String {
+///     format!("Session {}", session.0)
+/// }
+/// ```
+pub trait FromRequest: Sized {
+    fn from_request(request: &Request) -> Result<Self, Response>;
+}
+
+/// Anything that controller can return. Ctchi converts it into `Response` before sending.
+pub trait IntoResponse {
+    fn into_response(self) -> Response;
+}
+
+impl IntoResponse for Response {
+    fn into_response(self) -> Response {
+        self
+    }
+}
+
+/// Strings are rendered pages, so they become `200 OK` html responses.
+impl IntoResponse for String {
+    fn into_response(self) -> Response {
+        Response::html(self)
+    }
+}
+
+impl IntoResponse for &'static str {
+    fn into_response(self) -> Response {
+        Response::html(self.to_string())
+    }
+}
+
+impl<T: IntoResponse, E: IntoResponse> IntoResponse for Result<T, E> {
+    fn into_response(self) -> Response {
+        match self {
+            Ok(value) => value.into_response(),
+            Err(error) => error.into_response(),
+        }
+    }
+}
+
+/// Get standard reason phrase for status code. Unknown codes get empty phrase.
+///
+/// # Arguments:
+/// * `code` - HTTP status code
+pub fn status_reason(code: u16) -> &'static str {
+    match code {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        410 => "Gone",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        414 => "URI Too Long",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        422 => "Unprocessable Entity",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        _ => "",
+    }
+}
@@ -0,0 +1,61 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::http::{FromRequest, IntoResponse, Request, Response};
+
+/// JSON body of request or response.
+///
+/// As controller argument it deserializes request body. Request without JSON content type
+/// gets `415 Unsupported Media Type`, body which can't be deserialized into `T` gets
+/// `400 Bad Request`.
+///
+/// As controller result it serializes value with `Content-Type: application/json`.
+///
+/// # Example
+///
+/// ```rust
+/// use ctchi::core::json::Json;
+/// use ctchi::core::http::IntoResponse;
+/// use serde_json::json;
+///
+/// let response = Json(json!({"id": 42})).into_response();
+/// assert_eq!(response.code, 200);
+/// assert_eq!(response.header("content-type"), Some("application/json"));
+/// assert_eq!(response.content, br#"{"id":42}"#.to_vec());
+/// ```
+pub struct Json<T>(pub T);
+
+/// Check if media type is JSON, `application/json` or any `+json` suffix,
+/// e.g. `application/problem+json`.
+fn is_json(media_type: &str) -> bool {
+    media_type == "application/json" || media_type.ends_with("+json")
+}
+
+impl<T: DeserializeOwned> FromRequest for Json<T> {
+    fn from_request(request: &Request) -> Result<Json<T>, Response> {
+        match request.media_type() {
+            Some(media_type) if is_json(&media_type) => {}
+            _ => return Err(Response::new(415)),
+        }
+
+        serde_json::from_slice(&request.body).map(Json).map_err(|error| {
+            Response::new(400)
+                .with_header("Content-Type", "text/plain; charset=utf-8")
+                .with_content(error.to_string())
+        })
+    }
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        match serde_json::to_vec(&self.0) {
+            Ok(content) => Response::new(200)
+                .with_header("Content-Type", "application/json")
+                .with_content(content),
+            Err(error) => {
+                log::info!("Can't serialize response: {}", error);
+                Response::new(500)
+            }
+        }
+    }
+}
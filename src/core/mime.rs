@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+
+/// Type for files with unknown extension.
+pub const DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+
+/// Get MIME type by file extension, lower case and without dot.
+pub fn by_extension(extension: &str) -> Option<&'static str> {
+    let mime = match extension {
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "js" | "mjs" => "text/javascript",
+        "json" | "map" => "application/json",
+        "webmanifest" => "application/manifest+json",
+        "xml" => "application/xml",
+        "txt" => "text/plain",
+        "md" => "text/markdown",
+        "csv" => "text/csv",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "bmp" => "image/bmp",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => return None,
+    };
+
+    Some(mime)
+}
+
+/// Check if MIME type is text, so it needs charset.
+fn is_text(mime: &str) -> bool {
+    mime.starts_with("text/")
+        || mime.ends_with("+json")
+        || mime.ends_with("+xml")
+        || mime == "application/json"
+        || mime == "application/xml"
+        || mime == "application/javascript"
+}
+
+/// Get `Content-Type` value for file path. Extensions from `overrides` take
+/// precedence over built-in table, text types get `charset=utf-8`.
+///
+/// # Arguments:
+/// * `path` - file path or URL
+/// * `overrides` - extension (without dot) to MIME type map, e.g. from configuration
+///
+/// # Example
+///
+/// ```rust
+/// use ctchi::core::mime::content_type;
+/// use std::collections::HashMap;
+///
+/// let mut overrides = HashMap::new();
+/// overrides.insert("php".to_string(), "text/plain".to_string());
+///
+/// assert_eq!(content_type("/static/css/main.CSS", &overrides), "text/css; charset=utf-8");
+/// assert_eq!(content_type("/static/logo.png", &overrides), "image/png");
+/// assert_eq!(content_type("/static/index.php", &overrides), "text/plain; charset=utf-8");
+/// assert_eq!(content_type("/static/LICENSE", &overrides), "application/octet-stream");
+/// ```
+pub fn content_type(path: &str, overrides: &HashMap<String, String>) -> String {
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    let extension = match file_name.rsplit_once('.') {
+        Some((_, extension)) => extension.to_lowercase(),
+        None => String::new(),
+    };
+
+    let mime = overrides.get(&extension)
+        .map(|m| m.as_str())
+        .or_else(|| by_extension(&extension))
+        .unwrap_or(DEFAULT_MIME_TYPE);
+
+    if is_text(mime) && !mime.contains("charset") {
+        format!("{}; charset=utf-8", mime)
+    } else {
+        mime.to_string()
+    }
+}
@@ -2,4 +2,13 @@ pub mod app;
 pub mod routes;
 pub mod http;
 pub mod thread_pool;
-pub mod config;
\ No newline at end of file
+pub mod shutdown;
+pub mod config;
+pub mod url;
+pub mod form;
+pub mod multipart;
+pub mod mime;
+pub mod static_files;
+pub mod compression;
+#[cfg(feature = "json")]
+pub mod json;
\ No newline at end of file
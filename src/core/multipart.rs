@@ -0,0 +1,525 @@
+use std::cell::RefCell;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::env::temp_dir;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::config::{self, Config};
+use super::http::{FromRequest, Request, Response};
+
+/// Size limits for `multipart/form-data` bodies.
+#[derive(Clone, Debug)]
+pub struct MultipartLimits {
+    /// Maximum size of one uploaded file in bytes.
+    pub max_file_size: usize,
+    /// Maximum size of the whole multipart body in bytes.
+    pub max_total_size: usize,
+    /// Files bigger than that are written to temporary files instead of memory.
+    pub memory_size: usize,
+}
+
+impl MultipartLimits {
+    /// Limits from `multipart_*` configuration options.
+    pub fn from_config(config: &Config) -> MultipartLimits {
+        MultipartLimits {
+            max_file_size: config.multipart_max_file_size,
+            max_total_size: config.multipart_max_total_size,
+            memory_size: config.multipart_memory_size,
+        }
+    }
+}
+
+/// Why multipart body can't be parsed.
+#[derive(Debug, PartialEq)]
+pub enum MultipartError {
+    /// Body isn't `multipart/form-data` or there is no boundary.
+    NotMultipart,
+    /// Part delimiters or headers are broken.
+    Malformed,
+    /// Uploaded file is bigger than `max_file_size`, contains field name.
+    FileTooLarge(String),
+    /// All parts together are bigger than `max_total_size`.
+    TooLarge,
+    /// Temporary file can't be written.
+    Io(String),
+    /// Body can't be read from connection, e.g. client is too slow.
+    Read(ErrorKind),
+}
+
+impl fmt::Display for MultipartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultipartError::NotMultipart => write!(f, "Body isn't multipart/form-data"),
+            MultipartError::Malformed => write!(f, "Malformed multipart body"),
+            MultipartError::FileTooLarge(name) => write!(f, "File '{}' is too large", name),
+            MultipartError::TooLarge => write!(f, "Multipart body is too large"),
+            MultipartError::Io(error) => write!(f, "Can't store uploaded file: {}", error),
+            MultipartError::Read(kind) => write!(f, "Can't read multipart body: {:?}", kind),
+        }
+    }
+}
+
+/// Content of uploaded file.
+#[derive(Debug)]
+pub enum FileData {
+    Memory(Vec<u8>),
+    /// Temporary file, it's removed when `UploadedFile` is dropped.
+    Temp(PathBuf),
+}
+
+/// File from `<input type="file">`.
+#[derive(Debug)]
+pub struct UploadedFile {
+    /// File name sent by browser, it's untrusted and shouldn't be used as path as is.
+    pub filename: String,
+    pub content_type: String,
+    pub size: usize,
+    pub data: FileData,
+}
+
+impl UploadedFile {
+    /// Read file content into memory.
+    pub fn bytes(&self) -> io::Result<Vec<u8>> {
+        match &self.data {
+            FileData::Memory(bytes) => Ok(bytes.clone()),
+            FileData::Temp(path) => fs::read(path),
+        }
+    }
+
+    /// Save file content to `path`.
+    pub fn persist<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        match &self.data {
+            FileData::Memory(bytes) => fs::write(path, bytes),
+            FileData::Temp(temp) => fs::copy(temp, path).map(|_| ()),
+        }
+    }
+}
+
+impl Drop for UploadedFile {
+    fn drop(&mut self) {
+        if let FileData::Temp(path) = &self.data {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Parsed `multipart/form-data` body. Can be taken by controller as argument.
+///
+/// Request with another content type gets `415 Unsupported Media Type`, too big files
+/// get `413 Payload Too Large` and broken bodies get `400 Bad Request`.
+#[derive(Debug, Default)]
+pub struct Multipart {
+    pub fields: HashMap<String, Vec<String>>,
+    pub files: HashMap<String, Vec<UploadedFile>>,
+}
+
+impl Multipart {
+    /// Get the first value of text field.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields.get(name).and_then(|v| v.first()).map(|v| v.as_str())
+    }
+
+    /// Get the first file uploaded with field name.
+    pub fn file(&self, name: &str) -> Option<&UploadedFile> {
+        self.files.get(name).and_then(|v| v.first())
+    }
+
+    /// Parse multipart body of request with limits from configuration.
+    pub fn from_request_with_limits(
+        request: &Request,
+        limits: &MultipartLimits,
+    ) -> Result<Multipart, MultipartError> {
+        let content_type = request.header("content-type").unwrap_or("");
+        let boundary = boundary(content_type).ok_or(MultipartError::NotMultipart)?;
+
+        parse(&request.body, &boundary, limits)
+    }
+}
+
+impl FromRequest for Multipart {
+    fn from_request(request: &Request) -> Result<Multipart, Response> {
+        // bodies coming from connection are parsed while reading, see `enter`
+        if let Some(multipart) = UPLOADS.with(|uploads| uploads.borrow_mut().take()) {
+            return Ok(multipart);
+        }
+
+        let limits = MultipartLimits::from_config(&config::current());
+        Multipart::from_request_with_limits(request, &limits).map_err(|error| {
+            let code = match error {
+                MultipartError::NotMultipart => 415,
+                MultipartError::FileTooLarge(_) | MultipartError::TooLarge => 413,
+                MultipartError::Malformed | MultipartError::Read(_) => 400,
+                MultipartError::Io(_) => 500,
+            };
+            log::info!("{}", error);
+
+            Response::new(code)
+                .with_header("Content-Type", "text/plain; charset=utf-8")
+                .with_content(error.to_string())
+        })
+    }
+}
+
+/// Parse `multipart/form-data` body which is already in memory, see `parse_stream`.
+///
+/// # Arguments:
+/// * `body` - raw request body
+/// * `boundary` - boundary from `Content-Type` header, without leading dashes
+/// * `limits` - size limits
+///
+/// # Example
+///
+/// ```rust
+/// use ctchi::core::multipart::{parse, FileData, MultipartLimits, MultipartError};
+///
+/// let limits = MultipartLimits { max_file_size: 1024, max_total_size: 2048, memory_size: 512 };
+/// let body = b"--XyZ\r\n\
+///     Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+///     Holidays\r\n\
+///     --XyZ\r\n\
+///     Content-Disposition: form-data; name=\"photo\"; filename=\"sea.png\"\r\n\
+///     Content-Type: image/png\r\n\r\n\
+///     \x89PNG\r\n\
+///     --XyZ--\r\n";
+///
+/// let multipart = parse(body, "XyZ", &limits).unwrap();
+/// assert_eq!(multipart.field("title"), Some("Holidays"));
+/// let photo = multipart.file("photo").unwrap();
+/// assert_eq!(photo.filename, "sea.png");
+/// assert_eq!(photo.content_type, "image/png");
+/// assert_eq!(photo.bytes().unwrap(), b"\x89PNG".to_vec());
+///
+/// // files bigger than memory_size are kept in temporary files, removed on drop
+/// let spooled = MultipartLimits { max_file_size: 1024, max_total_size: 2048, memory_size: 2 };
+/// let multipart = parse(body, "XyZ", &spooled).unwrap();
+/// let photo = multipart.file("photo").unwrap();
+/// let path = match &photo.data {
+///     FileData::Temp(path) => path.clone(),
+///     FileData::Memory(_) => panic!("file should be written to disk"),
+/// };
+/// assert_eq!(std::fs::read(&path).unwrap(), b"\x89PNG".to_vec());
+/// drop(multipart);
+/// assert!(!path.exists());
+///
+/// let small = MultipartLimits { max_file_size: 2, max_total_size: 2048, memory_size: 512 };
+/// assert_eq!(parse(body, "XyZ", &small).unwrap_err(), MultipartError::FileTooLarge("photo".to_string()));
+/// ```
+pub fn parse(body: &[u8], boundary: &str, limits: &MultipartLimits) -> Result<Multipart, MultipartError> {
+    parse_stream(&mut &body[..], boundary, limits)
+}
+
+/// Parse `multipart/form-data` body while reading it. Only the tail which can be the beginning
+/// of delimiter is kept between reads, file content goes to memory or, when it gets bigger
+/// than `memory_size`, straight into temporary file. Limits are checked while reading, so
+/// too big body is rejected before it's received as a whole.
+///
+/// Reader should end where body ends, everything after closing delimiter is read and ignored.
+///
+/// # Arguments:
+/// * `reader` - body stream, e.g. connection limited by `Content-Length`
+/// * `boundary` - boundary from `Content-Type` header, without leading dashes
+/// * `limits` - size limits
+pub fn parse_stream<R: BufRead>(
+    reader: &mut R,
+    boundary: &str,
+    limits: &MultipartLimits,
+) -> Result<Multipart, MultipartError> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let part_end = format!("\r\n--{}", boundary).into_bytes();
+
+    let mut result = Multipart::default();
+    let mut left = limits.max_total_size;
+    let mut count = |size: usize| -> Result<(), MultipartError> {
+        left = left.checked_sub(size).ok_or(MultipartError::TooLarge)?;
+        Ok(())
+    };
+
+    // everything before the first delimiter is preamble and ignored
+    read_until(reader, &delimiter, |data| count(data.len()))?;
+
+    loop {
+        let mut next = [0; 2];
+        reader.read_exact(&mut next).map_err(read_error)?;
+        if &next == b"--" {
+            // epilogue is ignored too, but it's still a part of body
+            loop {
+                let size = reader.fill_buf().map_err(read_error)?.len();
+                if size == 0 {
+                    return Ok(result);
+                }
+                count(size)?;
+                reader.consume(size);
+            }
+        }
+        if &next != b"\r\n" {
+            return Err(MultipartError::Malformed);
+        }
+
+        let mut raw_headers = Vec::new();
+        read_until(reader, b"\r\n\r\n", |data| {
+            count(data.len())?;
+            raw_headers.extend_from_slice(data);
+            Ok(())
+        })?;
+        let headers = parse_part_headers(&raw_headers)?;
+
+        let disposition = headers.get("content-disposition").ok_or(MultipartError::Malformed)?;
+        let name = header_param(disposition, "name").ok_or(MultipartError::Malformed)?;
+
+        match header_param(disposition, "filename") {
+            Some(filename) => {
+                let mut file = FileWriter::new(limits.memory_size);
+                let mut size = 0;
+                read_until(reader, &part_end, |data| {
+                    count(data.len())?;
+                    size += data.len();
+                    if size > limits.max_file_size {
+                        return Err(MultipartError::FileTooLarge(name.clone()));
+                    }
+                    file.write(data)
+                })?;
+
+                let file = UploadedFile {
+                    filename,
+                    content_type: headers.get("content-type")
+                        .cloned()
+                        .unwrap_or_else(|| "application/octet-stream".to_string()),
+                    size,
+                    data: file.finish()?,
+                };
+                result.files.entry(name).or_default().push(file);
+            }
+            None => {
+                let mut value = Vec::new();
+                read_until(reader, &part_end, |data| {
+                    count(data.len())?;
+                    value.extend_from_slice(data);
+                    Ok(())
+                })?;
+                let value = String::from_utf8_lossy(&value).into_owned();
+                result.fields.entry(name).or_default().push(value);
+            }
+        }
+    }
+}
+
+/// Pass bytes to `sink` until `delimiter`, delimiter itself is consumed, but not passed.
+/// Body which ends before delimiter is malformed.
+fn read_until<R, F>(reader: &mut R, delimiter: &[u8], mut sink: F) -> Result<(), MultipartError>
+    where
+        R: BufRead,
+        F: FnMut(&[u8]) -> Result<(), MultipartError>,
+{
+    // tail of previous read which can be the beginning of delimiter
+    let mut pending = Vec::new();
+
+    loop {
+        let buffer = reader.fill_buf().map_err(read_error)?;
+        if buffer.is_empty() {
+            return Err(MultipartError::Malformed);
+        }
+        // in memory bodies give everything at once, they are walked piece by piece too
+        let read = buffer.len().min(64 * 1024);
+
+        let pending_size = pending.len();
+        let mut window = std::mem::take(&mut pending);
+        window.extend_from_slice(&buffer[..read]);
+
+        if let Some(position) = find(&window, delimiter, 0) {
+            sink(&window[..position])?;
+            reader.consume(position + delimiter.len() - pending_size);
+            return Ok(());
+        }
+
+        let keep = window.len().min(delimiter.len() - 1);
+        sink(&window[..window.len() - keep])?;
+        pending = window.split_off(window.len() - keep);
+        reader.consume(read);
+    }
+}
+
+fn read_error(error: io::Error) -> MultipartError {
+    match error.kind() {
+        ErrorKind::UnexpectedEof => MultipartError::Malformed,
+        kind => MultipartError::Read(kind),
+    }
+}
+
+/// Keeps small files in memory and moves big ones into temporary directory.
+struct FileWriter {
+    memory: Vec<u8>,
+    memory_size: usize,
+    temp: Option<(PathBuf, File)>,
+}
+
+impl FileWriter {
+    fn new(memory_size: usize) -> FileWriter {
+        FileWriter { memory: Vec::new(), memory_size, temp: None }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), MultipartError> {
+        if self.temp.is_none() && self.memory.len() + data.len() > self.memory_size {
+            let (path, mut file) = create_temp_file().map_err(|error| MultipartError::Io(error.to_string()))?;
+            let written = file.write_all(&self.memory);
+            // file is removed on drop if writing fails
+            self.temp = Some((path, file));
+            written.map_err(|error| MultipartError::Io(error.to_string()))?;
+            self.memory = Vec::new();
+        }
+
+        match &mut self.temp {
+            Some((_, file)) => file.write_all(data).map_err(|error| MultipartError::Io(error.to_string())),
+            None => {
+                self.memory.extend_from_slice(data);
+                Ok(())
+            }
+        }
+    }
+
+    fn finish(mut self) -> Result<FileData, MultipartError> {
+        match self.temp.take() {
+            Some((path, mut file)) => match file.flush() {
+                Ok(()) => Ok(FileData::Temp(path)),
+                Err(error) => {
+                    let _ = fs::remove_file(&path);
+                    Err(MultipartError::Io(error.to_string()))
+                }
+            },
+            None => Ok(FileData::Memory(std::mem::take(&mut self.memory))),
+        }
+    }
+}
+
+impl Drop for FileWriter {
+    /// Upload which wasn't finished leaves nothing in temporary directory.
+    fn drop(&mut self) {
+        if let Some((path, _)) = self.temp.take() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Create new temporary file with unpredictable name. `create_new` fails if the path exists,
+/// so file or symlink placed there by somebody else is never opened.
+fn create_temp_file() -> io::Result<(PathBuf, File)> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    for _ in 0..16 {
+        // every RandomState has random keys, so hash of anything is random
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_usize(COUNTER.fetch_add(1, Ordering::SeqCst));
+        let path = temp_dir().join(format!("ctchi-upload-{}-{:016x}", std::process::id(), hasher.finish()));
+
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        match options.open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error),
+        }
+    }
+
+    Err(io::Error::new(io::ErrorKind::AlreadyExists, "can't find free name for temporary file"))
+}
+
+/// Get boundary from `Content-Type` header of `multipart/form-data` body.
+pub(crate) fn boundary(content_type: &str) -> Option<String> {
+    let media_type = content_type.split(';').next().unwrap_or("").trim();
+    if !media_type.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    header_param(content_type, "boundary").filter(|boundary| !boundary.is_empty())
+}
+
+thread_local! {
+    static UPLOADS: RefCell<Option<Multipart>> = const { RefCell::new(None) };
+}
+
+/// Give body parsed while reading request to `Multipart` argument of controller on this
+/// thread. Uploads which weren't taken are dropped with returned guard, with their
+/// temporary files.
+pub(crate) fn enter(multipart: Multipart) -> UploadScope {
+    UPLOADS.with(|uploads| *uploads.borrow_mut() = Some(multipart));
+    UploadScope
+}
+
+pub(crate) struct UploadScope;
+
+impl Drop for UploadScope {
+    fn drop(&mut self) {
+        UPLOADS.with(|uploads| uploads.borrow_mut().take());
+    }
+}
+
+/// Parse part headers, names are in lower case.
+fn parse_part_headers(raw: &[u8]) -> Result<HashMap<String, String>, MultipartError> {
+    let text = std::str::from_utf8(raw).map_err(|_| MultipartError::Malformed)?;
+    let mut headers = HashMap::new();
+
+    for line in text.split("\r\n").filter(|l| !l.is_empty()) {
+        let (name, value) = line.split_once(':').ok_or(MultipartError::Malformed)?;
+        headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+    }
+
+    Ok(headers)
+}
+
+/// Get parameter of header value, e.g. `name` from `form-data; name="file"`.
+/// Quoted values can contain `;` and escaped quotes.
+fn header_param(value: &str, param: &str) -> Option<String> {
+    let mut rest = value;
+
+    while let Some(position) = rest.find(';') {
+        rest = rest[position + 1..].trim_start();
+        let (key, after_key) = rest.split_once('=')?;
+
+        let (param_value, after_value) = if let Some(quoted) = after_key.strip_prefix('"') {
+            let mut result = String::new();
+            let mut chars = quoted.char_indices();
+            let mut end = quoted.len();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' => {
+                        if let Some((_, escaped)) = chars.next() {
+                            result.push(escaped);
+                        }
+                    }
+                    '"' => {
+                        end = i + 1;
+                        break;
+                    }
+                    _ => result.push(c),
+                }
+            }
+            (result, &quoted[end..])
+        } else {
+            let end = after_key.find(';').unwrap_or(after_key.len());
+            (after_key[..end].trim().to_string(), &after_key[end..])
+        };
+
+        if key.trim().eq_ignore_ascii_case(param) {
+            return Some(param_value);
+        }
+        rest = after_value;
+    }
+
+    None
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    if from > haystack.len() {
+        return None;
+    }
+
+    haystack[from..].windows(needle.len())
+        .position(|window| window == needle)
+        .map(|position| position + from)
+}
@@ -1,48 +1,446 @@
+use std::collections::HashMap;
+
 use regex::Regex;
 
+use super::http::{HttpMethod, Request, Response};
+use super::url::{encode_path_segment, percent_decode};
+
 pub struct Route {
     pub path: String,
-    pub render_action: fn(&str) -> String,
+    pub method: HttpMethod,
+    pub render_action: fn(&Request) -> Response,
+}
+
+/// Result of route lookup for method and URI.
+pub enum RouteMatch<'a> {
+    /// Route for both path and method, with values of path parameters.
+    Found(&'a Route, HashMap<String, String>),
+    /// Path is known, but not for this method. Contains methods path is available for.
+    MethodNotAllowed(Vec<HttpMethod>),
+    /// Nothing matches, contains route which renders 404 page.
+    NotFound(&'a Route),
+    /// Route is found, but path differs from it in trailing slash and
+    /// `TrailingSlash::Redirect` policy is used. Contains canonical path.
+    Redirect(String),
+}
+
+/// What to do when request path and route differ only in trailing slash,
+/// e.g. `/blog/1` is requested, but route is `/blog/{id}/`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrailingSlash {
+    /// Path should be exactly the same as route, otherwise it's not found.
+    Strict,
+    /// Redirect client to path written as in route.
+    Redirect,
+    /// Trailing slash doesn't matter.
+    Lenient,
+}
+
+impl TrailingSlash {
+    /// Build policy from configuration value: `strict`, `redirect` or `lenient`.
+    pub fn parse(value: &str) -> Option<TrailingSlash> {
+        match value {
+            "strict" => Some(TrailingSlash::Strict),
+            "redirect" => Some(TrailingSlash::Redirect),
+            "lenient" => Some(TrailingSlash::Lenient),
+            _ => None,
+        }
+    }
+}
+
+/// One segment of route path, i.e. part between slashes.
+enum Segment {
+    /// Plain text, `blog` in `/blog/{id}/`
+    Static(String),
+    /// Any single segment, `{id}` in `/blog/{id}/`, or segment matching the
+    /// constraint, `{id:[0-9]+}` in `/blog/{id:[0-9]+}/`
+    Param(String, Option<Regex>),
+    /// The rest of the path, `{*file}` in `/files/{*file}`
+    Wildcard(String),
+}
+
+impl Segment {
+    fn parse(segment: &str) -> Segment {
+        if segment.starts_with('{') && segment.ends_with('}') {
+            let name = &segment[1..segment.len() - 1];
+            if let Some(name) = name.strip_prefix('*') {
+                return Segment::Wildcard(name.to_string());
+            }
+
+            match name.split_once(':') {
+                Some((name, constraint)) => {
+                    let regex = Regex::new(&format!("^(?:{})$", constraint))
+                        .unwrap_or_else(|error| panic!("Wrong constraint for '{}': {}", name, error));
+                    Segment::Param(name.to_string(), Some(regex))
+                }
+                None => Segment::Param(name.to_string(), None),
+            }
+        } else if segment.contains('{') || segment.contains('}') {
+            panic!("Route segment '{}' should be either plain text or a single parameter", segment)
+        } else {
+            Segment::Static(segment.to_string())
+        }
+    }
+}
+
+/// Child of route trie node for parameter segment.
+struct ParamNode {
+    name: String,
+    constraint: Option<Regex>,
+    node: Node,
+}
+
+impl ParamNode {
+    fn is_same(&self, name: &str, constraint: &Option<Regex>) -> bool {
+        self.name == name
+            && self.constraint.as_ref().map(|r| r.as_str()) == constraint.as_ref().map(|r| r.as_str())
+    }
+
+    fn accepts(&self, segment: &str) -> bool {
+        match &self.constraint {
+            Some(regex) => regex.is_match(segment),
+            None => true,
+        }
+    }
+}
+
+/// Node of route trie. Children are tried in order static, parameter, wildcard,
+/// so `/blog/new/` always wins over `/blog/{id}/`. Parameters are tried in order of
+/// registration, segments not matching parameter constraint are skipped.
+#[derive(Default)]
+struct Node {
+    statics: HashMap<String, Node>,
+    params: Vec<ParamNode>,
+    wildcards: Vec<(String, Vec<usize>)>,
+    /// Indexes of routes which end at this node, one per method and trailing slash,
+    /// e.g. both `/about` and `/about/` end at the same node.
+    routes: Vec<usize>,
+}
+
+impl Node {
+    fn insert(&mut self, segments: &[Segment]) -> &mut Vec<usize> {
+        match segments.split_first() {
+            None => &mut self.routes,
+            Some((Segment::Static(value), rest)) => {
+                self.statics.entry(value.to_string())
+                    .or_default()
+                    .insert(rest)
+            }
+            Some((Segment::Param(name, constraint), rest)) => {
+                let position = match self.params.iter().position(|p| p.is_same(name, constraint)) {
+                    Some(position) => position,
+                    None => {
+                        self.params.push(ParamNode {
+                            name: name.to_string(),
+                            constraint: constraint.clone(),
+                            node: Node::default(),
+                        });
+                        self.params.len() - 1
+                    }
+                };
+                self.params[position].node.insert(rest)
+            }
+            Some((Segment::Wildcard(name), rest)) => {
+                if !rest.is_empty() {
+                    panic!("Wildcard parameter '{}' should be the last segment of route", name);
+                }
+                let position = match self.wildcards.iter().position(|(n, _)| n == name) {
+                    Some(position) => position,
+                    None => {
+                        self.wildcards.push((name.to_string(), Vec::new()));
+                        self.wildcards.len() - 1
+                    }
+                };
+                &mut self.wildcards[position].1
+            }
+        }
+    }
+
+    /// Walk the trie and return route index of the first node (by priority) accepted
+    /// by `accept`. Parameters of the found path are collected into `params`.
+    fn find<'a, F>(
+        &'a self,
+        segments: &[&str],
+        params: &mut Vec<(&'a str, String)>,
+        accept: &mut F,
+    ) -> Option<usize>
+        where
+            F: FnMut(&[usize]) -> Option<usize>,
+    {
+        let (segment, rest) = match segments.split_first() {
+            None => return accept(&self.routes),
+            Some(split) => split,
+        };
+
+        if let Some(node) = self.statics.get(*segment) {
+            if let Some(found) = node.find(rest, params, accept) {
+                return Some(found);
+            }
+        }
+
+        if !segment.is_empty() {
+            for param in self.params.iter().filter(|p| p.accepts(segment)) {
+                params.push((&param.name, segment.to_string()));
+                if let Some(found) = param.node.find(rest, params, accept) {
+                    return Some(found);
+                }
+                params.pop();
+            }
+        }
+
+        for (name, routes) in &self.wildcards {
+            if let Some(found) = accept(routes) {
+                params.push((name, segments.join("/")));
+                return Some(found);
+            }
+        }
+
+        None
+    }
+}
+
+/// Check if route has the same trailing slash as request path, routes ending with
+/// wildcard match any path.
+fn same_slash(route: &Route, has_slash: bool) -> bool {
+    route.path.ends_with('/') == has_slash || route.path.contains("{*")
+}
+
+/// Split path into segments, leading and trailing slashes are ignored,
+/// routes keep trailing slash in their path.
+fn split_path(path: &str) -> Vec<&str> {
+    let trimmed = path.trim_start_matches('/');
+    let trimmed = trimmed.strip_suffix('/').unwrap_or(trimmed);
+    if trimmed.is_empty() {
+        Vec::new()
+    } else {
+        trimmed.split('/').collect()
+    }
+}
+
+/// Build path for redirect from request path segments with a single leading slash and
+/// trailing slash as in route. Request path is never echoed as is: `//evil.example` would be
+/// a protocol relative URL pointing to another host, browsers treat `\` as `/`, and control
+/// characters could break `Location` header, so everything outside `pchar` is encoded.
+fn canonical_path(uri: &str, route: &Route) -> String {
+    let segments = split_path(uri).iter()
+        .map(|segment| encode_path_segment(segment))
+        .collect::<Vec<String>>();
+
+    let mut path = format!("/{}", segments.join("/"));
+    if route.path.ends_with('/') && !path.ends_with('/') {
+        path.push('/');
+    }
+    path
 }
 
 pub struct Routes {
     routes: Vec<Route>,
+    root: Node,
+    not_found: Route,
+    trailing_slash: TrailingSlash,
+}
+
+fn not_found_action(_request: &Request) -> Response {
+    Response::new(404)
+        .with_header("Content-Type", "text/html; charset=utf-8")
+        .with_content("404 Not Found")
 }
 
 impl Routes {
     pub fn new() -> Routes {
         let mut routes = Routes {
             routes: Vec::new(),
+            root: Node::default(),
+            trailing_slash: TrailingSlash::Lenient,
+            not_found: Route {
+                path: "/404".to_string(),
+                method: HttpMethod::GET,
+                render_action: not_found_action,
+            },
         };
 
         routes.add_route(Route {
             path: "/404".to_string(),
-            render_action: |_url| {
-                "404 Not Found".to_string()
-            },
+            method: HttpMethod::GET,
+            render_action: not_found_action,
         });
 
         routes
     }
 
+    /// Add route into routing trie. Route path is parsed once here, so lookup
+    /// doesn't do any parsing or regex compilation.
+    ///
+    /// # Panics
+    ///
+    /// Panics if path has malformed parameter or there is already route with the same
+    /// path and method. Paths which differ only in trailing slash aren't the same.
     pub fn add_route(&mut self, route: Route) {
-        let url_replacer = Regex::new(r"(?P<first>\{.+?\})").unwrap();
-        let regex_url = url_replacer.replace_all(&route.path, ".+?");
-        let string = format!(r"^{}/?$", regex_url.to_string());
-        self.routes.push(Route {
-            path: string,
-            render_action: route.render_action,
-        });
+        let segments = split_path(&route.path).iter()
+            .map(|s| Segment::parse(s))
+            .collect::<Vec<Segment>>();
+
+        let routes = &mut self.routes;
+        let node_routes = self.root.insert(&segments);
+        let has_slash = route.path.ends_with('/');
+        if node_routes.iter().any(|&i| routes[i].method == route.method && routes[i].path.ends_with('/') == has_slash) {
+            panic!("Route {} {} is already registered", route.method.as_str(), route.path);
+        }
+
+        node_routes.push(routes.len());
+        routes.push(route);
+    }
+
+    /// Set policy for paths which differ from routes only in trailing slash.
+    /// Routes ending with wildcard are always lenient.
+    pub fn set_trailing_slash(&mut self, policy: TrailingSlash) {
+        self.trailing_slash = policy;
     }
 
-    pub fn get_route(&self, uri: &str) -> &Route {
-        for r in self.routes.iter() {
-            let regex = Regex::new(&r.path).unwrap();
-            if regex.is_match(uri) {
-                return r
+    /// Find route for request method and URI.
+    ///
+    /// `HEAD` requests are served by `GET` routes unless there is explicit `HEAD` route.
+    /// Route with the same trailing slash as path is preferred, route which differs only
+    /// in trailing slash is used according to `TrailingSlash` policy.
+    ///
+    /// # Arguments:
+    /// * `method` - request method
+    /// * `uri` - request path, every segment is percent decoded before matching
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use ctchi::core::routes::{Routes, Route, RouteMatch, TrailingSlash};
+    /// use ctchi::core::http::{HttpMethod, Request, Response};
+    ///
+    /// fn page(_request: &Request) -> Response {
+    ///     Response::new(200)
+    /// }
+    ///
+    /// let mut routes = Routes::new();
+    /// routes.add_route(Route { path: "/blog/{id}/".to_string(), method: HttpMethod::GET, render_action: page });
+    /// routes.add_route(Route { path: "/blog/new/".to_string(), method: HttpMethod::GET, render_action: page });
+    ///
+    /// match routes.get_route(&HttpMethod::GET, "/blog/42/") {
+    ///     RouteMatch::Found(route, params) => {
+    ///         assert_eq!(route.path, "/blog/{id}/");
+    ///         assert_eq!(params["id"], "42");
+    ///     }
+    ///     _ => panic!("route should be found"),
+    /// }
+    ///
+    /// // static segment beats parameter
+    /// match routes.get_route(&HttpMethod::GET, "/blog/new/") {
+    ///     RouteMatch::Found(route, _) => assert_eq!(route.path, "/blog/new/"),
+    ///     _ => panic!("route should be found"),
+    /// }
+    ///
+    /// // segments not matching constraint go to the next parameter
+    /// routes.add_route(Route { path: "/user/{id:[0-9]+}/".to_string(), method: HttpMethod::GET, render_action: page });
+    /// routes.add_route(Route { path: "/user/{name}/".to_string(), method: HttpMethod::GET, render_action: page });
+    /// match routes.get_route(&HttpMethod::GET, "/user/glotitude/") {
+    ///     RouteMatch::Found(route, params) => {
+    ///         assert_eq!(route.path, "/user/{name}/");
+    ///         assert_eq!(params["name"], "glotitude");
+    ///     }
+    ///     _ => panic!("route should be found"),
+    /// }
+    ///
+    /// // by default trailing slash doesn't matter
+    /// assert!(matches!(routes.get_route(&HttpMethod::GET, "/blog/42"), RouteMatch::Found(_, _)));
+    ///
+    /// routes.set_trailing_slash(TrailingSlash::Redirect);
+    /// match routes.get_route(&HttpMethod::GET, "/blog/42") {
+    ///     RouteMatch::Redirect(path) => assert_eq!(path, "/blog/42/"),
+    ///     _ => panic!("client should be redirected"),
+    /// }
+    ///
+    /// // location is built from segments, so it can't point to another host
+    /// routes.add_route(Route { path: "/{slug}/".to_string(), method: HttpMethod::GET, render_action: page });
+    /// match routes.get_route(&HttpMethod::GET, "//evil.example") {
+    ///     RouteMatch::Redirect(path) => assert_eq!(path, "/evil.example/"),
+    ///     _ => panic!("client should be redirected"),
+    /// }
+    /// match routes.get_route(&HttpMethod::GET, "/\\evil.example") {
+    ///     RouteMatch::Redirect(path) => assert_eq!(path, "/%5Cevil.example/"),
+    ///     _ => panic!("client should be redirected"),
+    /// }
+    /// match routes.get_route(&HttpMethod::GET, "/a\rSet-Cookie:x=1") {
+    ///     RouteMatch::Redirect(path) => assert_eq!(path, "/a%0DSet-Cookie:x=1/"),
+    ///     _ => panic!("client should be redirected"),
+    /// }
+    ///
+    /// routes.set_trailing_slash(TrailingSlash::Strict);
+    /// assert!(matches!(routes.get_route(&HttpMethod::GET, "/blog/42"), RouteMatch::NotFound(_)));
+    ///
+    /// match routes.get_route(&HttpMethod::POST, "/blog/42/") {
+    ///     RouteMatch::MethodNotAllowed(allowed) => assert_eq!(allowed, vec![HttpMethod::GET, HttpMethod::HEAD]),
+    ///     _ => panic!("method shouldn't be allowed"),
+    /// }
+    ///
+    /// // routes can differ only in trailing slash
+    /// routes.add_route(Route { path: "/about".to_string(), method: HttpMethod::GET, render_action: page });
+    /// routes.add_route(Route { path: "/about/".to_string(), method: HttpMethod::GET, render_action: page });
+    /// for path in ["/about", "/about/"] {
+    ///     match routes.get_route(&HttpMethod::GET, path) {
+    ///         RouteMatch::Found(route, _) => assert_eq!(route.path, path),
+    ///         _ => panic!("route should be found"),
+    ///     }
+    /// }
+    /// ```
+    pub fn get_route(&self, method: &HttpMethod, uri: &str) -> RouteMatch<'_> {
+        let decoded = split_path(uri).iter()
+            .map(|s| percent_decode(s, false))
+            .collect::<Vec<String>>();
+        let segments = decoded.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+        let mut params = Vec::new();
+        let mut allowed = Vec::new();
+        let has_slash = uri.ends_with('/');
+        let strict = self.trailing_slash == TrailingSlash::Strict;
+
+        let routes = &self.routes;
+        let found = self.root.find(&segments, &mut params, &mut |candidates: &[usize]| {
+            // strict policy doesn't see routes which differ in trailing slash at all
+            let usable = |i: usize| !strict || same_slash(&routes[i], has_slash);
+            let find_method = |m: &HttpMethod| {
+                let by_method = || candidates.iter().copied().filter(|&i| &routes[i].method == m);
+                by_method().find(|&i| same_slash(&routes[i], has_slash))
+                    .or_else(|| by_method().find(|&i| usable(i)))
+            };
+
+            for &i in candidates.iter().filter(|&&i| usable(i)) {
+                if !allowed.contains(&routes[i].method) {
+                    allowed.push(routes[i].method);
+                }
             }
+
+            match find_method(method) {
+                Some(i) => Some(i),
+                None if *method == HttpMethod::HEAD => find_method(&HttpMethod::GET),
+                None => None,
+            }
+        });
+
+        if let Some(index) = found {
+            let route = &self.routes[index];
+            if self.trailing_slash == TrailingSlash::Redirect && !same_slash(route, has_slash) {
+                return RouteMatch::Redirect(canonical_path(uri, route));
+            }
+
+            let params = params.into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect();
+            return RouteMatch::Found(route, params);
+        }
+
+        if allowed.is_empty() {
+            return RouteMatch::NotFound(&self.not_found);
+        }
+
+        if allowed.contains(&HttpMethod::GET) && !allowed.contains(&HttpMethod::HEAD) {
+            allowed.push(HttpMethod::HEAD);
         }
 
-        self.get_route("/404")
+        RouteMatch::MethodNotAllowed(allowed)
     }
 }
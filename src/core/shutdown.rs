@@ -0,0 +1,133 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Handle to stop running application. It can be cloned and sent to other threads.
+///
+/// After `shutdown` server stops accepting connections, answers requests which are already
+/// accepted with `Connection: close` and waits for them at most `shutdown_timeout` seconds.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use ctchi::core::app::Ctchi;
+/// use ctchi::core::routes::Routes;
+/// use std::thread;
+///
+/// let server = Ctchi::new(Routes::new());
+/// let shutdown = server.shutdown_handle();
+///
+/// let running = thread::spawn(move || server.start());
+/// // ... run tests against the server
+/// shutdown.shutdown();
+/// running.join().unwrap().unwrap();
+/// ```
+#[derive(Clone, Default)]
+pub struct ShutdownHandle {
+    requested: Arc<AtomicBool>,
+    address: Arc<Mutex<Option<SocketAddr>>>,
+}
+
+impl ShutdownHandle {
+    pub fn new() -> ShutdownHandle {
+        ShutdownHandle::default()
+    }
+
+    /// Ask server to stop. It's safe to call it several times and before server is started.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use ctchi::core::app::Ctchi;
+    /// use ctchi::core::config::Config;
+    ///
+    /// let config = Config { bind_path: "127.0.0.1:0".to_string(), workers: 1, ..Config::default() };
+    /// let server = Ctchi::builder().config(config).build();
+    /// server.shutdown_handle().shutdown();
+    /// // returns right away instead of waiting for connections
+    /// server.start().unwrap();
+    /// ```
+    pub fn shutdown(&self) {
+        if self.requested.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        // listener blocks in accept, so wake it up with empty connection
+        if let Some(address) = *self.address.lock().unwrap_or_else(PoisonError::into_inner) {
+            if let Err(error) = TcpStream::connect(address) {
+                log::info!("Can't wake up listener: {}", error);
+            }
+        }
+    }
+
+    /// Check if shutdown was requested.
+    pub fn is_shutdown(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Address server is listening on, `None` until it's started. Handy with port `0`
+    /// in `bind_path`, when port is chosen by system. Unspecified IP (`0.0.0.0`) is
+    /// replaced with loopback one.
+    pub fn address(&self) -> Option<SocketAddr> {
+        *self.address.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Remember address server is listening on, so `shutdown` can wake it up.
+    pub(crate) fn set_address(&self, address: SocketAddr) {
+        // nobody can connect to 0.0.0.0, but listener bound to it accepts local connections
+        let ip = match address.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            ip => ip,
+        };
+        *self.address.lock().unwrap_or_else(PoisonError::into_inner) = Some(SocketAddr::new(ip, address.port()));
+    }
+}
+
+/// Signal handlers of running server. They are removed on drop, so signals sent after
+/// server is stopped don't kill process. signal-hook doesn't restore default handler,
+/// so such signals are ignored.
+#[cfg(unix)]
+pub(crate) struct SignalHandlers {
+    handle: signal_hook::iterator::Handle,
+    ids: Vec<signal_hook::SigId>,
+}
+
+#[cfg(unix)]
+impl Drop for SignalHandlers {
+    fn drop(&mut self) {
+        self.handle.close();
+        for id in self.ids.drain(..) {
+            signal_hook::low_level::unregister(id);
+        }
+    }
+}
+
+/// Call `shutdown` on `SIGTERM` or `SIGINT`. The second signal kills process right away
+/// for the case when some requests hang.
+///
+/// Returned handlers should be dropped when server is stopped, otherwise the next signal
+/// would kill process right away as if it was the second one.
+#[cfg(unix)]
+pub(crate) fn on_signals(shutdown: &ShutdownHandle) -> std::io::Result<SignalHandlers> {
+    use signal_hook::consts::{SIGINT, SIGTERM};
+    use signal_hook::flag;
+    use signal_hook::iterator::Signals;
+
+    let mut ids = Vec::new();
+    for signal in [SIGTERM, SIGINT] {
+        ids.push(flag::register_conditional_default(signal, Arc::clone(&shutdown.requested))?);
+    }
+
+    let mut signals = Signals::new([SIGTERM, SIGINT])?;
+    let handle = signals.handle();
+    let shutdown = shutdown.clone();
+    std::thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            log::info!("Got signal {}, shutting down", signal);
+            shutdown.shutdown();
+        }
+    });
+
+    Ok(SignalHandlers { handle, ids })
+}
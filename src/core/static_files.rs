@@ -0,0 +1,367 @@
+use std::fs::{File, Metadata};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, TimeZone, Utc};
+
+use super::compression::{self, Encoding};
+use super::config::Config;
+use super::http::{HttpMethod, Request, Response};
+use super::mime;
+use super::url;
+
+/// Serve static file. Files can be only read, other methods than `GET` and `HEAD`
+/// get `405 Method Not Allowed`.
+///
+/// Response has `ETag` and `Last-Modified` headers built from file metadata, conditional
+/// requests with `If-None-Match` or `If-Modified-Since` get `304 Not Modified` if file
+/// hasn't changed. Requests with `Range` header get `206 Partial Content` with requested
+/// part of file (or `multipart/byteranges` for several ranges), or `416 Range Not Satisfiable`.
+/// Ranges are cut to `max_range_size` bytes in total. File isn't read into memory, it's copied
+/// to connection while response is written, only `multipart/byteranges` body is built in memory.
+///
+/// If there is precompressed sibling (`main.css.br` or `main.css.gz` for `main.css`) and client
+/// accepts its encoding, sibling is sent with `Content-Encoding` and type of the original file.
+///
+/// # Arguments:
+/// * `request` - request for the file
+/// * `root` - directory with static files, nothing outside of it is served
+/// * `file_pth` - URL path of the file relative to `root`
+/// * `config` - configuration with MIME types, `Cache-Control` rules and range size limit
+pub fn serve(request: &Request, root: &Path, file_pth: &str, config: &Config) -> Response {
+    if request.method != HttpMethod::GET && request.method != HttpMethod::HEAD {
+        return Response::new(405).with_header("Allow", "GET, HEAD");
+    }
+
+    let path = match url::resolve_path(root, file_pth) {
+        Some(path) => path,
+        None => {
+            log::info!("Static file {} isn't found in {}", file_pth, root.display());
+            return Response::new(404);
+        }
+    };
+
+    let precompressed = Encoding::ALL.iter()
+        .filter_map(|encoding| {
+            let sibling = url::resolve_path(root, &format!("{}.{}", file_pth, encoding.extension()?))?;
+            Some((*encoding, sibling))
+        })
+        .filter(|(_, sibling)| sibling.is_file())
+        .collect::<Vec<_>>();
+    let encoding = request.header("accept-encoding").and_then(|accept| {
+        compression::negotiate(accept, &precompressed.iter().map(|(e, _)| *e).collect::<Vec<_>>())
+    });
+    let path = match precompressed.iter().find(|(e, _)| Some(*e) == encoding) {
+        Some((_, sibling)) if path.is_file() => sibling.clone(),
+        _ => path,
+    };
+
+    let result = File::open(&path).and_then(|file| {
+        let metadata = file.metadata()?;
+        if metadata.is_dir() {
+            // directory listing isn't supported
+            return Ok(Response::new(403));
+        }
+
+        let mut headers = Response::new(200);
+        let modified = modified_time(&metadata);
+        headers.set_header("ETag", &etag(&metadata, modified, encoding));
+        if !precompressed.is_empty() {
+            compression::add_vary(&mut headers, "Accept-Encoding");
+        }
+        if let Some(modified) = modified {
+            headers.set_header("Last-Modified", &http_date(modified));
+        }
+        if let Some(cache_control) = cache_control(&request.url, &config.cache_control) {
+            headers.set_header("Cache-Control", cache_control);
+        }
+
+        if is_not_modified(request, &headers, modified) {
+            headers.code = 304;
+            return Ok(headers);
+        }
+
+        headers.set_header("Accept-Ranges", "bytes");
+        if let Some(encoding) = encoding {
+            headers.set_header("Content-Encoding", encoding.as_str());
+        }
+        let content_type = mime::content_type(file_pth, &config.mime_types);
+
+        let range = match request.header("range") {
+            Some(range) if is_range_fresh(request, &headers) => parse_range(range, metadata.len()),
+            _ => RangeRequest::Full,
+        };
+
+        match range {
+            RangeRequest::Full => {
+                Ok(headers.with_header("Content-Type", &content_type).with_file(file, 0, metadata.len()))
+            }
+            RangeRequest::Unsatisfiable => {
+                headers.code = 416;
+                Ok(headers.with_header("Content-Range", &format!("bytes */{}", metadata.len())))
+            }
+            RangeRequest::Partial(ranges) => {
+                headers.code = 206;
+                let ranges = limit_ranges(ranges, config.max_range_size);
+                read_ranges(file, &ranges, metadata.len(), &content_type, headers)
+            }
+        }
+    });
+
+    result.unwrap_or_else(|error| {
+        log::info!("Can't read static file {}: {}", path.display(), error);
+        match error.kind() {
+            ErrorKind::PermissionDenied => Response::new(403),
+            _ => Response::new(404),
+        }
+    })
+}
+
+/// Modification time in whole seconds, HTTP dates don't have better precision.
+fn modified_time(metadata: &Metadata) -> Option<DateTime<Utc>> {
+    let seconds = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_secs();
+    Utc.timestamp_opt(seconds as i64, 0).single()
+}
+
+/// Strong validator from file size and modification time, every encoding has its own.
+fn etag(metadata: &Metadata, modified: Option<DateTime<Utc>>, encoding: Option<Encoding>) -> String {
+    let modified = modified.map(|m| m.timestamp()).unwrap_or(0);
+    match encoding {
+        Some(encoding) => format!("\"{:x}-{:x}-{}\"", metadata.len(), modified, encoding.as_str()),
+        None => format!("\"{:x}-{:x}\"", metadata.len(), modified),
+    }
+}
+
+/// Format date as `Sun, 06 Nov 1994 08:49:37 GMT`.
+fn http_date(date: DateTime<Utc>) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Check conditional request headers. `If-None-Match` takes precedence over
+/// `If-Modified-Since`, as RFC 7232 requires.
+fn is_not_modified(request: &Request, response: &Response, modified: Option<DateTime<Utc>>) -> bool {
+    if let Some(if_none_match) = request.header("if-none-match") {
+        let etag = response.header("etag").unwrap_or("");
+        return if_none_match.split(',')
+            .map(|tag| tag.trim())
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag);
+    }
+
+    match (request.header("if-modified-since"), modified) {
+        (Some(since), Some(modified)) => match DateTime::parse_from_rfc2822(since) {
+            Ok(since) => modified <= since,
+            Err(_) => false,
+        },
+        _ => false,
+    }
+}
+
+/// Requested part of file.
+#[derive(Debug, PartialEq)]
+pub enum RangeRequest {
+    /// No or unsupported range, whole file should be sent.
+    Full,
+    /// Inclusive byte ranges, `(0, 499)` is the first 500 bytes.
+    Partial(Vec<(u64, u64)>),
+    /// No range overlaps the file.
+    Unsatisfiable,
+}
+
+/// More ranges than that are treated as abuse and whole file is sent.
+const MAX_RANGES: usize = 32;
+
+/// Parse `Range` header value. Syntactically wrong ranges are ignored as RFC 7233 requires,
+/// ranges which start after the end of file are skipped.
+///
+/// # Arguments:
+/// * `header` - `Range` header value
+/// * `len` - file length in bytes
+///
+/// # Example
+///
+/// ```rust
+/// use ctchi::core::static_files::{parse_range, RangeRequest};
+///
+/// assert_eq!(parse_range("bytes=0-499", 1000), RangeRequest::Partial(vec![(0, 499)]));
+/// assert_eq!(parse_range("bytes=500-", 1000), RangeRequest::Partial(vec![(500, 999)]));
+/// assert_eq!(parse_range("bytes=-100", 1000), RangeRequest::Partial(vec![(900, 999)]));
+/// assert_eq!(parse_range("bytes=900-2000", 1000), RangeRequest::Partial(vec![(900, 999)]));
+/// assert_eq!(
+///     parse_range("bytes=0-0, 10-19", 1000),
+///     RangeRequest::Partial(vec![(0, 0), (10, 19)])
+/// );
+/// assert_eq!(parse_range("bytes=1000-", 1000), RangeRequest::Unsatisfiable);
+/// assert_eq!(parse_range("bytes=5-1", 1000), RangeRequest::Full);
+/// assert_eq!(parse_range("items=0-1", 1000), RangeRequest::Full);
+/// assert_eq!(parse_range("bytes=", 1000), RangeRequest::Full);
+/// ```
+pub fn parse_range(header: &str, len: u64) -> RangeRequest {
+    let specs = match header.trim().strip_prefix("bytes=") {
+        Some(specs) => specs,
+        None => return RangeRequest::Full,
+    };
+
+    let specs = specs.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()).collect::<Vec<&str>>();
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return RangeRequest::Full;
+    }
+
+    let mut ranges = Vec::new();
+    for spec in specs {
+        let (start, end) = match spec.split_once('-') {
+            Some(bounds) => bounds,
+            None => return RangeRequest::Full,
+        };
+
+        let range = match (start.parse::<u64>(), end.parse::<u64>()) {
+            // last N bytes
+            (Err(_), Ok(suffix)) if start.is_empty() => {
+                if suffix == 0 || len == 0 {
+                    None
+                } else {
+                    Some((len.saturating_sub(suffix), len - 1))
+                }
+            }
+            (Ok(start), Err(_)) if end.is_empty() => {
+                if start < len { Some((start, len - 1)) } else { None }
+            }
+            (Ok(start), Ok(end)) if start <= end => {
+                if start < len { Some((start, end.min(len - 1))) } else { None }
+            }
+            _ => return RangeRequest::Full,
+        };
+
+        ranges.extend(range);
+    }
+
+    if ranges.is_empty() {
+        RangeRequest::Unsatisfiable
+    } else {
+        RangeRequest::Partial(ranges)
+    }
+}
+
+/// Cut ranges so they have at most `max_size` bytes together. Server may send less than
+/// requested, `Content-Range` tells client which part it got. The first range always keeps
+/// at least one byte.
+///
+/// # Example
+///
+/// ```rust
+/// use ctchi::core::static_files::limit_ranges;
+///
+/// assert_eq!(limit_ranges(vec![(0, 99)], 1000), vec![(0, 99)]);
+/// assert_eq!(limit_ranges(vec![(500, 4_000_000_000)], 1000), vec![(500, 1499)]);
+/// assert_eq!(limit_ranges(vec![(0, 599), (1000, 1599), (2000, 2099)], 1000), vec![(0, 599), (1000, 1399)]);
+/// ```
+pub fn limit_ranges(ranges: Vec<(u64, u64)>, max_size: u64) -> Vec<(u64, u64)> {
+    let mut left = max_size.max(1);
+    let mut result = Vec::new();
+    for (start, end) in ranges {
+        if left == 0 {
+            break;
+        }
+        let end = end.min(start.saturating_add(left - 1));
+        left -= end - start + 1;
+        result.push((start, end));
+    }
+    result
+}
+
+/// `If-Range` makes range request conditional: if file has changed since client got
+/// its part, whole file should be sent.
+fn is_range_fresh(request: &Request, response: &Response) -> bool {
+    match request.header("if-range") {
+        Some(validator) if validator.starts_with('"') || validator.starts_with("W/") => {
+            // weak validators can't be used for ranges
+            Some(validator) == response.header("etag")
+        }
+        Some(date) => Some(date) == response.header("last-modified"),
+        None => true,
+    }
+}
+
+/// Build `206 Partial Content` response. One range is sent from file as is,
+/// several ranges are read into `multipart/byteranges` body.
+fn read_ranges(
+    mut file: File,
+    ranges: &[(u64, u64)],
+    len: u64,
+    content_type: &str,
+    response: Response,
+) -> io::Result<Response> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    if let [(start, end)] = *ranges {
+        return Ok(response
+            .with_header("Content-Type", content_type)
+            .with_header("Content-Range", &format!("bytes {}-{}/{}", start, end, len))
+            .with_file(file, start, end - start + 1));
+    }
+
+    let mut read_range = |(start, end): (u64, u64)| -> io::Result<Vec<u8>> {
+        let mut part = vec![0; (end - start + 1) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut part)?;
+        Ok(part)
+    };
+
+    let boundary = format!(
+        "ctchi-{:x}-{:x}",
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0),
+        COUNTER.fetch_add(1, Ordering::SeqCst)
+    );
+
+    let mut content = Vec::new();
+    for &(start, end) in ranges {
+        content.extend_from_slice(format!(
+            "--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+            boundary, content_type, start, end, len
+        ).as_bytes());
+        content.append(&mut read_range((start, end))?);
+        content.extend_from_slice(b"\r\n");
+    }
+    content.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+
+    Ok(response
+        .with_header("Content-Type", &format!("multipart/byteranges; boundary={}", boundary))
+        .with_content(content))
+}
+
+/// Find `Cache-Control` value for URL path. Rules which start with `/` are path prefixes
+/// and the longest matching prefix wins, other rules are file extensions. Prefixes take
+/// precedence over extensions.
+///
+/// # Example
+///
+/// ```rust
+/// use ctchi::core::static_files::cache_control;
+///
+/// let rules = vec![
+///     ("css".to_string(), "max-age=3600".to_string()),
+///     ("/static/".to_string(), "no-cache".to_string()),
+///     ("/static/img/".to_string(), "max-age=86400, immutable".to_string()),
+/// ];
+/// assert_eq!(cache_control("/static/img/logo.png", &rules), Some("max-age=86400, immutable"));
+/// assert_eq!(cache_control("/static/main.css", &rules), Some("no-cache"));
+/// assert_eq!(cache_control("/assets/main.css", &rules), Some("max-age=3600"));
+/// assert_eq!(cache_control("/assets/main.js", &rules), None);
+/// ```
+pub fn cache_control<'a>(url_path: &str, rules: &'a [(String, String)]) -> Option<&'a str> {
+    let by_prefix = rules.iter()
+        .filter(|(selector, _)| selector.starts_with('/') && url_path.starts_with(selector.as_str()))
+        .max_by_key(|(selector, _)| selector.len());
+
+    if let Some((_, value)) = by_prefix {
+        return Some(value);
+    }
+
+    let file_name = url_path.rsplit('/').next().unwrap_or(url_path);
+    let extension = file_name.rsplit_once('.').map(|(_, e)| e.to_lowercase())?;
+
+    rules.iter()
+        .find(|(selector, _)| !selector.starts_with('/') && selector.eq_ignore_ascii_case(&extension))
+        .map(|(_, value)| value.as_str())
+}
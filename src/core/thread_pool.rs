@@ -1,9 +1,83 @@
 use std::thread;
-use std::sync::{mpsc, Arc, Mutex};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// What to do with new connection when queue of the pool is full.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QueueOverflow {
+    /// Stop accepting connections until some worker is free.
+    Block,
+    /// Answer `503 Service Unavailable` right away.
+    Reject,
+    /// Close connection without answer.
+    Drop,
+}
+
+impl QueueOverflow {
+    /// Parse configuration value: `block`, `reject` or `drop`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use ctchi::core::thread_pool::QueueOverflow;
+    ///
+    /// assert_eq!(QueueOverflow::parse("Reject"), Some(QueueOverflow::Reject));
+    /// assert_eq!(QueueOverflow::parse("wait"), None);
+    /// ```
+    pub fn parse(value: &str) -> Option<QueueOverflow> {
+        match value.trim().to_lowercase().as_str() {
+            "block" => Some(QueueOverflow::Block),
+            "reject" => Some(QueueOverflow::Reject),
+            "drop" => Some(QueueOverflow::Drop),
+            _ => None,
+        }
+    }
+}
+
+/// Number of jobs waiting for free worker. It can be cloned and read from other threads,
+/// e.g. for monitoring.
+#[derive(Clone, Debug, Default)]
+pub struct QueueDepth {
+    depth: Arc<AtomicUsize>,
+    /// Producer waits on it when queue is full, workers wake it up after taking a job.
+    space: Arc<(Mutex<()>, Condvar)>,
+}
+
+impl QueueDepth {
+    pub fn new() -> QueueDepth {
+        QueueDepth::default()
+    }
+
+    /// Current number of queued jobs.
+    pub fn get(&self) -> usize {
+        self.depth.load(Ordering::SeqCst)
+    }
+
+    fn pop(&self) {
+        self.depth.fetch_sub(1, Ordering::SeqCst);
+        let (lock, space) = &*self.space;
+        let _guard = lock.lock().unwrap_or_else(PoisonError::into_inner);
+        space.notify_one();
+    }
+
+    fn wait_for_space(&self, capacity: usize) {
+        let (lock, space) = &*self.space;
+        let mut guard = lock.lock().unwrap_or_else(PoisonError::into_inner);
+        while self.get() >= capacity {
+            guard = space.wait(guard).unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
 
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: mpsc::Sender<Job>,
+    workers: Mutex<Vec<Worker>>,
+    /// `None` after `join`, workers stop when channel is closed.
+    sender: Option<mpsc::Sender<Job>>,
+    receiver: Arc<Mutex<mpsc::Receiver<Job>>>,
+    capacity: usize,
+    queue: QueueDepth,
 }
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
@@ -16,31 +90,192 @@ impl ThreadPool {
     /// # Panics
     ///
     /// The `new` function will panic if the size is zero.
-    pub fn new(capacity: usize) -> ThreadPool {
+    pub fn new(size: usize) -> ThreadPool {
+        ThreadPool::with_queue(size, usize::MAX, QueueDepth::new())
+    }
+
+    /// Create a new ThreadPool with bounded queue.
+    ///
+    /// # Arguments:
+    /// * `size` - number of threads in the pool
+    /// * `capacity` - maximum number of jobs waiting for free worker, `execute` blocks
+    ///   when queue is full
+    /// * `queue` - queue depth counter, clone of it can be kept for monitoring
+    ///
+    /// # Panics
+    ///
+    /// Panics if size or capacity is zero.
+    pub fn with_queue(size: usize, capacity: usize, queue: QueueDepth) -> ThreadPool {
+        assert!(size > 0);
         assert!(capacity > 0);
 
-        let mut workers = Vec::with_capacity(capacity);
+        let mut workers = Vec::with_capacity(size);
 
         let (sender, receiver) = mpsc::channel();
 
         let receiver = Arc::new(Mutex::new(receiver));
 
-        for i in 0..capacity {
-            workers.push(Worker::new(i, Arc::clone(&receiver)));
+        for i in 0..size {
+            workers.push(Worker::new(i, Arc::clone(&receiver), queue.clone()));
         }
 
         ThreadPool {
-            workers,
-            sender
+            workers: Mutex::new(workers),
+            sender: Some(sender),
+            receiver,
+            capacity,
+            queue,
         }
     }
 
+    /// Number of jobs waiting for free worker.
+    pub fn queue_depth(&self) -> usize {
+        self.queue.get()
+    }
+
+    /// Check if queue is full, so `execute` would block.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use ctchi::core::thread_pool::{QueueDepth, ThreadPool};
+    /// use std::sync::mpsc;
+    ///
+    /// let pool = ThreadPool::with_queue(1, 1, QueueDepth::new());
+    /// let (started, is_started) = mpsc::channel();
+    /// let (release, is_released) = mpsc::channel::<()>();
+    /// // the only worker is busy
+    /// pool.execute(move || {
+    ///     started.send(()).unwrap();
+    ///     is_released.recv().unwrap();
+    /// });
+    /// is_started.recv().unwrap();
+    ///
+    /// assert!(!pool.is_full());
+    /// pool.execute(|| {});
+    /// assert_eq!(pool.queue_depth(), 1);
+    /// assert!(pool.is_full());
+    ///
+    /// release.send(()).unwrap();
+    /// ```
+    pub fn is_full(&self) -> bool {
+        self.queue.get() >= self.capacity
+    }
+
+    /// Run job on one of the workers, waits while queue is full. Panic inside job doesn't
+    /// kill the worker, and workers which died anyway are replaced before the job is queued.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use ctchi::core::thread_pool::ThreadPool;
+    /// use std::sync::mpsc;
+    ///
+    /// # std::panic::set_hook(Box::new(|_| {}));
+    /// let pool = ThreadPool::new(2);
+    /// for _ in 0..10 {
+    ///     pool.execute(|| panic!("broken handler"));
+    /// }
+    ///
+    /// let (sender, receiver) = mpsc::channel();
+    /// for i in 0..4 {
+    ///     let sender = sender.clone();
+    ///     pool.execute(move || sender.send(i).unwrap());
+    /// }
+    ///
+    /// let mut results = receiver.iter().take(4).collect::<Vec<i32>>();
+    /// results.sort();
+    /// assert_eq!(results, vec![0, 1, 2, 3]);
+    /// ```
     pub fn execute<F>(&self, f: F)
         where
             F: FnOnce() + Send + 'static,
     {
+        self.respawn_dead_workers();
+
         let job = Box::new(f);
-        self.sender.send(job).unwrap();
+        match &self.sender {
+            Some(sender) => {
+                self.queue.wait_for_space(self.capacity);
+                self.queue.depth.fetch_add(1, Ordering::SeqCst);
+                sender.send(job).unwrap();
+            }
+            None => log::info!("Thread pool is stopped, job is dropped"),
+        }
+    }
+
+    /// Stop taking new jobs and wait until workers finish jobs which are already queued.
+    /// Workers which are still busy after `timeout` are left running in background.
+    ///
+    /// Returns `true` if all workers are finished.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use ctchi::core::thread_pool::ThreadPool;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    ///
+    /// let mut pool = ThreadPool::new(2);
+    /// let done = Arc::new(AtomicUsize::new(0));
+    /// for _ in 0..4 {
+    ///     let done = Arc::clone(&done);
+    ///     pool.execute(move || {
+    ///         std::thread::sleep(Duration::from_millis(10));
+    ///         done.fetch_add(1, Ordering::SeqCst);
+    ///     });
+    /// }
+    ///
+    /// assert!(pool.join(Duration::from_secs(5)));
+    /// assert_eq!(done.load(Ordering::SeqCst), 4);
+    /// ```
+    pub fn join(&mut self, timeout: Duration) -> bool {
+        drop(self.sender.take());
+
+        let deadline = Instant::now() + timeout;
+        let workers = self.workers.get_mut().unwrap_or_else(PoisonError::into_inner);
+        while workers.iter().any(|worker| !worker.thread.is_finished()) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let mut all_finished = true;
+        for worker in workers.drain(..) {
+            if worker.thread.is_finished() {
+                let _ = worker.thread.join();
+            } else {
+                log::info!("Worker {} is still busy, leaving it", worker.id);
+                all_finished = false;
+            }
+        }
+
+        all_finished
+    }
+
+    fn respawn_dead_workers(&self) {
+        if self.sender.is_none() {
+            return;
+        }
+
+        let mut workers = self.workers.lock().unwrap_or_else(PoisonError::into_inner);
+        for worker in workers.iter_mut() {
+            if worker.thread.is_finished() {
+                log::info!("Worker {} is dead, starting new one", worker.id);
+                *worker = Worker::new(worker.id, Arc::clone(&self.receiver), self.queue.clone());
+            }
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    /// Wait for all queued jobs without time limit.
+    fn drop(&mut self) {
+        drop(self.sender.take());
+
+        let workers = self.workers.get_mut().unwrap_or_else(PoisonError::into_inner);
+        for worker in workers.drain(..) {
+            let _ = worker.thread.join();
+        }
     }
 }
 
@@ -50,13 +285,22 @@ struct Worker {
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
+    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>, queue: QueueDepth) -> Worker {
         let thread = thread::spawn(move || loop {
-            let job = receiver.lock().unwrap().recv().unwrap();
+            // lock is poisoned only if other worker panicked while holding it,
+            // receiver itself is still fine
+            let job = match receiver.lock().unwrap_or_else(PoisonError::into_inner).recv() {
+                Ok(job) => job,
+                // pool is gone
+                Err(_) => break,
+            };
+            queue.pop();
 
-            job();
+            if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                log::info!("Job panicked in worker {}", id);
+            }
         });
 
         Worker { id, thread }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Decode `%XX` sequences. Malformed sequences are left as is, bytes which
+/// aren't valid UTF-8 after decoding are replaced with `U+FFFD`.
+///
+/// # Arguments:
+/// * `input` - encoded string
+/// * `plus_as_space` - decode `+` as space, it's true for query strings and forms,
+///   but not for paths
+///
+/// # Example
+///
+/// ```rust
+/// use ctchi::core::url::percent_decode;
+/// assert_eq!(percent_decode("hello%20world%21", false), "hello world!");
+/// assert_eq!(percent_decode("a+b", true), "a b");
+/// assert_eq!(percent_decode("a+b", false), "a+b");
+/// assert_eq!(percent_decode("100%", false), "100%");
+/// ```
+pub fn percent_decode(input: &str, plus_as_space: bool) -> String {
+    let bytes = input.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() && is_hex_pair(bytes[i + 1], bytes[i + 2]) => {
+                result.push(hex_value(bytes[i + 1]) * 16 + hex_value(bytes[i + 2]));
+                i += 3;
+            }
+            b'+' if plus_as_space => {
+                result.push(b' ');
+                i += 1;
+            }
+            b => {
+                result.push(b);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&result).into_owned()
+}
+
+/// Percent-encode path segment, every byte outside of RFC 3986 `pchar` is encoded.
+/// `%XX` sequences which are already there are kept, so encoded segment isn't encoded twice.
+///
+/// # Example
+///
+/// ```rust
+/// use ctchi::core::url::encode_path_segment;
+/// assert_eq!(encode_path_segment("a b%20c"), "a%20b%20c");
+/// assert_eq!(encode_path_segment("x\rSet-Cookie:y=1"), "x%0DSet-Cookie:y=1");
+/// assert_eq!(encode_path_segment("100%"), "100%25");
+/// assert_eq!(encode_path_segment("\\evil"), "%5Cevil");
+/// ```
+pub fn encode_path_segment(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut result = String::with_capacity(bytes.len());
+
+    for (i, &b) in bytes.iter().enumerate() {
+        let is_pchar = b.is_ascii_alphanumeric() || b"-._~!$&'()*+,;=:@".contains(&b);
+        let is_encoded = b == b'%' && i + 2 < bytes.len() && is_hex_pair(bytes[i + 1], bytes[i + 2]);
+        if is_pchar || is_encoded {
+            result.push(b as char);
+        } else {
+            result.push_str(&format!("%{:02X}", b));
+        }
+    }
+
+    result
+}
+
+fn is_hex_pair(first: u8, second: u8) -> bool {
+    first.is_ascii_hexdigit() && second.is_ascii_hexdigit()
+}
+
+fn hex_value(digit: u8) -> u8 {
+    match digit {
+        b'0'..=b'9' => digit - b'0',
+        b'a'..=b'f' => digit - b'a' + 10,
+        _ => digit - b'A' + 10,
+    }
+}
+
+/// Parse `application/x-www-form-urlencoded` string (query string or form body)
+/// into multimap. Values of repeated keys are kept in order, key without `=` gets
+/// empty value.
+///
+/// # Example
+///
+/// ```rust
+/// use ctchi::core::url::parse_query;
+/// let query = parse_query("q=rust+web&tag=a&tag=b%26c&empty");
+/// assert_eq!(query["q"], vec!["rust web"]);
+/// assert_eq!(query["tag"], vec!["a", "b&c"]);
+/// assert_eq!(query["empty"], vec![""]);
+/// ```
+pub fn parse_query(query: &str) -> HashMap<String, Vec<String>> {
+    let mut result = HashMap::<String, Vec<String>>::new();
+
+    for pair in query.split('&').filter(|p| !p.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        result.entry(percent_decode(key, true))
+            .or_default()
+            .push(percent_decode(value, true));
+    }
+
+    result
+}
+
+/// Normalize URL path into relative file path: percent decode it, drop empty and `.`
+/// segments and apply `..` segments. Returns `None` if path tries to go above its root
+/// or contains characters which are never valid in served files.
+///
+/// # Example
+///
+/// ```rust
+/// use ctchi::core::url::normalize_path;
+/// assert_eq!(normalize_path("/css/./main.css"), Some("css/main.css".to_string()));
+/// assert_eq!(normalize_path("/css/../img/logo%20big.png"), Some("img/logo big.png".to_string()));
+/// assert_eq!(normalize_path("/../../etc/passwd"), None);
+/// assert_eq!(normalize_path("/css/../../etc/passwd"), None);
+/// assert_eq!(normalize_path("/..%2f..%2fetc/passwd"), None);
+/// assert_eq!(normalize_path("/..%2F..%2Fetc%2Fpasswd"), None);
+/// assert_eq!(normalize_path("/%2e%2e/%2e%2e/etc/passwd"), None);
+/// assert_eq!(normalize_path("/..\\..\\etc\\passwd"), None);
+/// assert_eq!(normalize_path("/..%5c..%5cetc%5cpasswd"), None);
+/// assert_eq!(normalize_path("/passwd%00.css"), None);
+/// ```
+pub fn normalize_path(path: &str) -> Option<String> {
+    normalize_file_path(&percent_decode(path, false))
+}
+
+/// Normalize file path the same way as `normalize_path`, but without percent decoding,
+/// for names which don't come from URL as is, e.g. template names.
+///
+/// # Example
+///
+/// ```rust
+/// use ctchi::core::url::normalize_file_path;
+/// assert_eq!(normalize_file_path("pages/./100%25.html"), Some("pages/100%25.html".to_string()));
+/// assert_eq!(normalize_file_path("pages/../../etc/passwd"), None);
+/// assert_eq!(normalize_file_path("pages\\..\\..\\etc\\passwd"), None);
+/// ```
+pub fn normalize_file_path(path: &str) -> Option<String> {
+    if path.contains('\0') || path.contains('\\') {
+        return None;
+    }
+
+    let mut segments = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            _ => segments.push(segment),
+        }
+    }
+
+    Some(segments.join("/"))
+}
+
+/// Map URL path to file inside `root`. Path is normalized with `normalize_path` and then
+/// canonicalized, so neither `..` nor symlinks can lead outside of `root`.
+/// Returns `None` if file doesn't exist or is outside of `root`.
+///
+/// # Arguments:
+/// * `root` - directory files are served from
+/// * `path` - URL path relative to `root`, still percent encoded
+///
+/// # Example
+///
+/// ```rust
+/// use ctchi::core::url::resolve_path;
+/// use std::fs;
+///
+/// let root = std::env::temp_dir().join("ctchi-resolve-path-example");
+/// fs::create_dir_all(root.join("css")).unwrap();
+/// fs::write(root.join("css/main.css"), "body {}").unwrap();
+///
+/// assert!(resolve_path(&root, "/css/main.css").unwrap().ends_with("css/main.css"));
+/// assert_eq!(resolve_path(&root, "/css/missing.css"), None);
+/// assert_eq!(resolve_path(&root, "/css/..%2f..%2f..%2fetc/passwd"), None);
+/// ```
+pub fn resolve_path(root: &Path, path: &str) -> Option<PathBuf> {
+    resolve_file_path(root, &percent_decode(path, false))
+}
+
+/// Map file path to file inside `root` the same way as `resolve_path`, but without
+/// percent decoding, so `%` in file names is taken literally.
+///
+/// # Arguments:
+/// * `root` - directory files are taken from
+/// * `path` - file path relative to `root`
+///
+/// # Example
+///
+/// ```rust
+/// use ctchi::core::url::resolve_file_path;
+/// use std::fs;
+///
+/// let root = std::env::temp_dir().join("ctchi-resolve-file-path-example");
+/// fs::create_dir_all(&root).unwrap();
+/// fs::write(root.join("100%.html"), "<p>Done</p>").unwrap();
+///
+/// assert!(resolve_file_path(&root, "100%.html").unwrap().ends_with("100%.html"));
+/// assert_eq!(resolve_file_path(&root, "100%25.html"), None);
+/// assert_eq!(resolve_file_path(&root, "../../etc/passwd"), None);
+/// ```
+pub fn resolve_file_path(root: &Path, path: &str) -> Option<PathBuf> {
+    let relative = normalize_file_path(path)?;
+    let root = root.canonicalize().ok()?;
+    let resolved = root.join(relative).canonicalize().ok()?;
+
+    if resolved.starts_with(&root) {
+        Some(resolved)
+    } else {
+        None
+    }
+}
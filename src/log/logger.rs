@@ -4,10 +4,15 @@ use chrono::{Datelike, Timelike, Utc};
 use std::fs::{File, OpenOptions};
 use std::io::prelude::*;
 use std::path::Path;
+use std::sync::OnceLock;
 
-use crate::core::config::get_configuration;
+use crate::core::config::{self, get_configuration};
 
-struct SimpleLogger;
+struct SimpleLogger {
+    /// Log file passed to the first `init_with_path`, used outside of applications' threads.
+    /// Without it `log_path` of global configuration is used.
+    log_path: OnceLock<String>,
+}
 
 impl log::Log for SimpleLogger {
     fn enabled(&self, metadata: &Metadata) -> bool {
@@ -24,10 +29,18 @@ impl log::Log for SimpleLogger {
             );
             println!("{}", log_message);
 
-            let config_reader = get_configuration();
-            let config = config_reader.inner.lock().unwrap();
-            let log_path = config.log_path.to_string();
-            drop(config);
+            // threads of application write to its own log file
+            let log_path = match config::entered() {
+                Some(config) => config.log_path.clone(),
+                None => match self.log_path.get() {
+                    Some(log_path) => log_path.clone(),
+                    None => {
+                        let config_reader = get_configuration();
+                        let config = config_reader.inner.lock().unwrap();
+                        config.log_path.to_string()
+                    }
+                },
+            };
 
             // fixme remove unwrap
             let mut file = OpenOptions::new()
@@ -44,9 +57,21 @@ impl log::Log for SimpleLogger {
 }
 
 
-static LOGGER: SimpleLogger = SimpleLogger;
+static LOGGER: SimpleLogger = SimpleLogger { log_path: OnceLock::new() };
 
+/// Set up global logger writing to `log_path` of global configuration.
+/// Threads of running applications write to `log_path` of their own configuration.
 pub fn init() -> Result<(), SetLoggerError> {
     log::set_logger(&LOGGER)
         .map(|()| log::set_max_level(LevelFilter::Debug))
+}
+
+/// Set up global logger writing to `log_path` instead of path from global configuration,
+/// only the first path has effect.
+///
+/// # Arguments:
+/// * `log_path` - log file for threads which don't belong to running application
+pub fn init_with_path(log_path: &str) -> Result<(), SetLoggerError> {
+    let _ = LOGGER.log_path.set(log_path.to_string());
+    init()
 }
\ No newline at end of file
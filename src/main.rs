@@ -2,6 +2,7 @@
 mod utils;
 
 use ctchi::core::app::Ctchi;
+use ctchi::core::http::Response;
 use ctchi::core::routes::{Routes, Route};
 
 use ctchi_codegen::route;
@@ -9,7 +10,7 @@ use std::collections::HashMap;
 use ctchi::templates::parser::Context;
 
 #[route("/")]
-fn index()-> String {
+fn index()-> Result<String, Response> {
     let mut context = HashMap::<String, Context>::new();
     context.insert("test".to_string(), Context::BooleanValue(true));
     context.insert("my_name".to_string(), Context::SingleValue("Leonid Toshchev".to_string()));
@@ -19,7 +20,7 @@ fn index()-> String {
 }
 
 #[route("/blog/{id}/")]
-fn blog(id: &str) -> String {
+fn blog(id: u32) -> Result<String, Response> {
     let page = &format!("blog/{}.html", id);
     render!(page)
 }
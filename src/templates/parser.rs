@@ -1,6 +1,11 @@
-use crate::core::config::get_configuration;
+use crate::core::config;
+use crate::core::http::Response;
+use crate::core::url::resolve_file_path;
+use crate::templates::writer;
 
 use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
 use regex::Regex;
 use std::collections::HashMap;
 use std::collections::hash_map::RandomState;
@@ -166,8 +171,11 @@ pub struct ImportTag {
 
 impl WithContent for ImportTag {
     fn get_content(&self, context: &HashMap<String, Context>) -> Vec<u8> {
-        let node = parse_file(&self.path);
-        node.get_content(context)
+        // page is already being rendered, so missing part is just left out
+        match parse_file(&self.path) {
+            Ok(node) => node.get_content(context),
+            Err(_) => Vec::new(),
+        }
     }
 }
 
@@ -218,14 +226,58 @@ impl WithContent for Html {
     }
 }
 
-pub fn parse_file(path: &str) -> TemplateNode {
-    let config_reader = get_configuration();
-    let config = config_reader.inner.lock().unwrap();
-    let page = format!("{}/{}", config.base_path, path);
-    drop(config);
-    let content = fs::read_to_string(page).unwrap_or_else(|error| { error.to_string() });
+/// Parse template file from `base_path`. Template name can be built from URL, so it can't
+/// point outside of `base_path`. Name isn't percent decoded, router already decodes
+/// path parameters, so `%` is taken literally.
+/// `base_path` is taken from configuration of application handling request.
+///
+/// Missing templates and templates outside of `base_path` give `ErrorKind::NotFound`.
+pub fn parse_file(path: &str) -> io::Result<TemplateNode> {
+    let base_path = config::current().base_path.to_string();
+
+    let page = match resolve_file_path(Path::new(&base_path), path) {
+        Some(page) => page,
+        None => {
+            // name can come from URL, so it's logged escaped
+            log::info!("Template {:?} isn't found in {}", path, base_path);
+            return Err(io::Error::new(ErrorKind::NotFound, "template isn't found"));
+        }
+    };
 
-    parse(&content)
+    let content = fs::read_to_string(&page).map_err(|error| {
+        log::info!("Can't read template {}: {}", page.display(), error);
+        error
+    })?;
+
+    Ok(parse(&content))
+}
+
+/// Render template file with context, used by `render!` macro. Missing template gives
+/// `404 Not Found` response and unreadable one gives `500 Internal Server Error`, so
+/// controller can return the result as is or use `?`.
+///
+/// # Arguments:
+/// * `path` - template path relative to `base_path`
+/// * `context` - values for template tags
+///
+/// # Example
+///
+/// ```rust
+/// use std::collections::HashMap;
+/// use ctchi::templates::parser::render_file;
+///
+/// let context = HashMap::new();
+/// assert_eq!(render_file("missing.html", &context).unwrap_err().code, 404);
+/// assert_eq!(render_file("../../Cargo.toml", &context).unwrap_err().code, 404);
+/// // names are taken literally, they are never decoded again
+/// assert_eq!(render_file("..%2f..%2fCargo.toml", &context).unwrap_err().code, 404);
+/// ```
+pub fn render_file(path: &str, context: &HashMap<String, Context>) -> Result<String, Response> {
+    match parse_file(path) {
+        Ok(node) => Ok(writer::write(&node, context)),
+        Err(error) if error.kind() == ErrorKind::NotFound => Err(Response::new(404)),
+        Err(_) => Err(Response::new(500)),
+    }
 }
 
 pub fn parse(html: &str) -> TemplateNode {
@@ -1,25 +1,15 @@
+/// Render template from `base_path`, result is `Result<String, Response>`: missing
+/// template gives `404 Not Found` response. Older versions returned `String`.
 #[macro_export]
 macro_rules! render {
     ($x:tt) => {
         {
-            use ctchi::templates::parser;
-            use ctchi::templates::writer;
-
-            let tag = parser::parse_file($x);
-            let context = HashMap::new();
-            let result = writer::write(&tag, &context);
-            result
+            let context = std::collections::HashMap::new();
+            ctchi::templates::parser::render_file($x, &context)
         }
     };
     ($x:tt, $c:ident) => {
-        {
-            use ctchi::templates::parser;
-            use ctchi::templates::writer;
-
-            let tag = parser::parse_file($x);
-            let result = writer::write(&tag, &$c);
-            result
-        }
+        ctchi::templates::parser::render_file($x, &$c)
     }
 }
 
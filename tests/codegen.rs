@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+use ctchi::core::form::Form;
+use ctchi::core::http::{FromRequest, HttpMethod, Request, Response};
+use ctchi::core::routes::{Route, RouteMatch, Routes};
+use ctchi_codegen::{post, route, FromForm};
+
+#[route("/")]
+fn index() -> String {
+    "index".to_string()
+}
+
+#[route("/blog/{id}/")]
+fn blog(id: u32, request: &Request) -> String {
+    format!("blog {} {:?}", id, request.method)
+}
+
+#[route("/num/{id:[0-9]+}/")]
+fn num(id: u64) -> String {
+    format!("num {}", id + 1)
+}
+
+#[route("/search/")]
+fn search(q: &str, page: Option<u32>, tag: Option<String>) -> String {
+    format!("{} {:?} {:?}", q, page, tag)
+}
+
+#[route("/items/", method = "put")]
+fn put_item() -> Response {
+    Response::new(201)
+}
+
+#[route("/check/{x}/")]
+fn check(x: &str) -> Result<String, Response> {
+    if x == "ok" { Ok("fine".to_string()) } else { Err(Response::new(400)) }
+}
+
+#[derive(FromForm)]
+struct Contact {
+    name: String,
+    age: u32,
+    note: Option<String>,
+    tags: Vec<String>,
+    subscribe: bool,
+}
+
+#[post("/contact/")]
+fn contact(Form(contact): Form<Contact>) -> String {
+    format!("{} {} {:?} {:?} {}", contact.name, contact.age, contact.note, contact.tags, contact.subscribe)
+}
+
+struct Session(String);
+
+impl FromRequest for Session {
+    fn from_request(request: &Request) -> Result<Session, Response> {
+        match request.header("cookie") {
+            Some(cookie) => Ok(Session(cookie.to_string())),
+            None => Err(Response::new(401)),
+        }
+    }
+}
+
+#[route("/account/")]
+fn account(#[from_request] session: Session) -> String {
+    format!("session {}", session.0)
+}
+
+fn routes() -> Routes {
+    let mut routes = Routes::new();
+    routes.add_route(ctchi_routing_index());
+    routes.add_route(ctchi_routing_blog());
+    routes.add_route(ctchi_routing_num());
+    routes.add_route(ctchi_routing_search());
+    routes.add_route(ctchi_routing_put_item());
+    routes.add_route(ctchi_routing_check());
+    routes.add_route(ctchi_routing_contact());
+    routes.add_route(ctchi_routing_account());
+    routes
+}
+
+fn request(method: HttpMethod, uri: &str, headers: &[(&str, &str)], body: &str) -> Request {
+    let (url, query_string) = uri.split_once('?').unwrap_or((uri, ""));
+    Request {
+        method,
+        url: url.to_string(),
+        version: "HTTP/1.1".to_string(),
+        query_string: query_string.to_string(),
+        headers: headers.iter().map(|(k, v)| (k.to_lowercase(), v.to_string())).collect(),
+        body: body.as_bytes().to_vec(),
+        params: HashMap::new(),
+    }
+}
+
+/// Route request the same way server does and give status with body.
+fn call(mut request: Request) -> (u16, String) {
+    let routes = routes();
+    let response = match routes.get_route(&request.method, &request.url) {
+        RouteMatch::Found(route, params) => {
+            request.params = params;
+            (route.render_action)(&request)
+        }
+        RouteMatch::NotFound(route) => (route.render_action)(&request),
+        RouteMatch::MethodNotAllowed(_) => Response::new(405),
+        RouteMatch::Redirect(_) => Response::new(301),
+    };
+    (response.code, String::from_utf8(response.content).unwrap())
+}
+
+fn get(uri: &str) -> (u16, String) {
+    call(request(HttpMethod::GET, uri, &[], ""))
+}
+
+fn post_form(content_type: &str, body: &str) -> (u16, String) {
+    call(request(HttpMethod::POST, "/contact/", &[("Content-Type", content_type)], body))
+}
+
+#[test]
+fn plain_routes() {
+    assert_eq!(get("/"), (200, "index".to_string()));
+    assert_eq!(get("/check/ok/"), (200, "fine".to_string()));
+    assert_eq!(get("/check/no/").0, 400);
+    assert_eq!(get("/missing/").0, 404);
+}
+
+#[test]
+fn typed_path_params() {
+    assert_eq!(get("/blog/42/"), (200, "blog 42 GET".to_string()));
+    // value which can't be parsed into argument type means there is no such page
+    assert_eq!(get("/blog/abc/").0, 404);
+    assert_eq!(get("/blog/99999999999/").0, 404);
+
+    assert_eq!(get("/num/7/"), (200, "num 8".to_string()));
+    assert_eq!(get("/num/x7/").0, 404);
+}
+
+#[test]
+fn query_args() {
+    assert_eq!(get("/search/?q=rust"), (200, "rust None None".to_string()));
+    assert_eq!(get("/search/?q=a%20b&page=2&tag=web"), (200, "a b Some(2) Some(\"web\")".to_string()));
+    assert_eq!(get("/search/").0, 400);
+    assert_eq!(get("/search/?q=rust&page=two").0, 400);
+}
+
+#[test]
+fn method_argument() {
+    assert_eq!(call(request(HttpMethod::PUT, "/items/", &[], "")).0, 201);
+    assert_eq!(get("/items/").0, 405);
+}
+
+#[test]
+fn form_argument() {
+    let form = "application/x-www-form-urlencoded";
+    let (code, body) = post_form(form, "name=Leonid+T&age=30&tags=a&tags=b&subscribe=on");
+    assert_eq!(code, 200);
+    assert_eq!(body, "Leonid T 30 None [\"a\", \"b\"] true");
+    let (code, body) = post_form(form, "name=Leonid&age=30&note=hi");
+    assert_eq!((code, body.as_str()), (200, "Leonid 30 Some(\"hi\") [] false"));
+
+    assert_eq!(post_form(form, "name=Leonid").0, 400);
+    assert_eq!(post_form(form, "name=Leonid&age=old").0, 400);
+    assert_eq!(post_form("application/json", "{\"name\": \"Leonid\"}").0, 415);
+}
+
+#[test]
+fn from_request_argument() {
+    let (code, body) = call(request(HttpMethod::GET, "/account/", &[("Cookie", "abc")], ""));
+    assert_eq!((code, body.as_str()), (200, "session abc"));
+    assert_eq!(get("/account/").0, 401);
+}